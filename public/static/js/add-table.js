@@ -0,0 +1,41 @@
+const list = document.getElementById("extra-list");
+
+function getAllValues() {
+    let values = [];
+    let lis = list.getElementsByTagName("li");
+    for (let i = 0; i < lis.length; i++) {
+        values.push(lis[i].getElementsByTagName("input").item(0).value);
+    }
+    return values;
+}
+
+function onInput() {
+    let extraInput = document.getElementById("extra-input");
+    let newValue = extraInput.value;
+    let maxValue = parseInt(extraInput.max);
+    if (newValue >= maxValue) {
+        extraInput.value = maxValue;
+        newValue = maxValue;
+    }
+
+    let values = getAllValues();
+    list.innerHTML = "";
+
+    for (let i = 0; i < newValue; i++) {
+        let input = document.createElement("input");
+        input.type = "text";
+        input.name = "extra[" + i + "]";
+        input.placeholder = "Column name";
+        input.required = true;
+        if (values[i]) {
+            input.value = values[i];
+        }
+
+        let li = document.createElement("li");
+        li.appendChild(input);
+        list.appendChild(li);
+    }
+}
+
+document.getElementById("extra-input").addEventListener("input", onInput);
+onInput();
@@ -0,0 +1,141 @@
+const operations = document.getElementById('api-operations');
+const schemas = document.getElementById('api-schemas');
+const tokenInput = document.getElementById('api-token');
+
+function element(tag, text, className) {
+    let el = document.createElement(tag);
+    if (text) {
+        el.textContent = text;
+    }
+    if (className) {
+        el.className = className;
+    }
+    return el;
+}
+
+function schemaName(schema) {
+    if (!schema) {
+        return '';
+    }
+    if (schema.$ref) {
+        return schema.$ref.split('/').pop();
+    }
+    if (schema.type === 'array') {
+        return schemaName(schema.items) + '[]';
+    }
+    if (schema.type === 'object' && schema.additionalProperties) {
+        return 'Map<string, ' + schemaName(schema.additionalProperties) + '>';
+    }
+    return schema.format || schema.type || 'any';
+}
+
+function jsonSchema(content) {
+    return content && content['application/json'] ? content['application/json'].schema : null;
+}
+
+async function send(method, path, operation, form, output) {
+    let query = new URLSearchParams();
+    for (let param of operation.parameters) {
+        let value = form.elements[param.name].value;
+        if (param.in === 'path') {
+            path = path.replace('{' + param.name + '}', encodeURIComponent(value));
+        } else if (value !== '') {
+            query.append(param.name, value);
+        }
+    }
+    let options = {method: method.toUpperCase(), headers: {}};
+    if (tokenInput.value) {
+        options.headers['Authorization'] = 'Bearer ' + tokenInput.value;
+    }
+    if (operation.requestBody) {
+        options.headers['Content-Type'] = 'application/json';
+        options.body = form.elements['body'].value;
+    }
+    let url = query.toString() ? path + '?' + query : path;
+    output.textContent = 'Sending...';
+    try {
+        let response = await fetch(url, options);
+        let text = await response.text();
+        try {
+            text = JSON.stringify(JSON.parse(text), null, 2);
+        } catch (e) {
+            //Empty or not JSON, shown as it is
+        }
+        output.textContent = response.status + ' ' + response.statusText + '\n' + text;
+    } catch (e) {
+        output.textContent = e.toString();
+    }
+}
+
+function renderOperation(method, path, operation) {
+    let details = element('details', null, 'operation');
+    let summary = element('summary');
+    summary.appendChild(element('span', method, 'method'));
+    summary.appendChild(element('code', path));
+    summary.appendChild(document.createTextNode(' ' + operation.summary));
+    details.appendChild(summary);
+    if (operation.description) {
+        details.appendChild(element('p', operation.description));
+    }
+    if (operation.security && operation.security.length === 0) {
+        details.appendChild(element('p', 'No token required.'));
+    }
+
+    let form = element('form');
+    for (let param of operation.parameters) {
+        let label = param.name + ' (' + param.in + ', ' + schemaName(param.schema) + (param.required ? ', required' : '') + ')';
+        form.appendChild(element('label', label));
+        if (param.description) {
+            form.appendChild(element('p', param.description));
+        }
+        let input = element('input');
+        input.type = 'text';
+        input.name = param.name;
+        input.required = param.required;
+        form.appendChild(input);
+    }
+    if (operation.requestBody) {
+        form.appendChild(element('label', 'Body (' + schemaName(jsonSchema(operation.requestBody.content)) + ')'));
+        let body = element('textarea');
+        body.name = 'body';
+        body.value = '{}';
+        form.appendChild(body);
+    }
+    for (let [status, response] of Object.entries(operation.responses)) {
+        let name = schemaName(jsonSchema(response.content));
+        form.appendChild(element('p', status + ': ' + response.description + (name ? ' (' + name + ')' : '')));
+    }
+    let submit = element('input');
+    submit.type = 'submit';
+    submit.value = 'Send';
+    form.appendChild(submit);
+    let output = element('pre', null, 'hidden');
+    form.addEventListener('submit', function (e) {
+        e.preventDefault();
+        output.classList.remove('hidden');
+        send(method, path, operation, form, output);
+    });
+    details.appendChild(form);
+    details.appendChild(output);
+    operations.appendChild(details);
+}
+
+async function load() {
+    let response = await fetch('/api/openapi.json');
+    let spec = await response.json();
+    document.getElementById('api-title').textContent = spec.info.title + ' ' + spec.info.version;
+    for (let [path, item] of Object.entries(spec.paths)) {
+        for (let [method, operation] of Object.entries(item)) {
+            renderOperation(method, path, operation);
+        }
+    }
+    schemas.appendChild(element('h2', 'Schemas'));
+    for (let [name, schema] of Object.entries(spec.components.schemas)) {
+        let details = element('details', null, 'operation');
+        details.appendChild(element('summary', name));
+        details.appendChild(element('pre', JSON.stringify(schema, null, 2)));
+        schemas.appendChild(details);
+    }
+}
+
+load();
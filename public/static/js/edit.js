@@ -0,0 +1,8 @@
+const showDeleteFormButton = document.getElementById('show-delete-form-button');
+
+if (showDeleteFormButton) {
+    showDeleteFormButton.addEventListener('click', function () {
+        document.getElementById('delete-form').classList.remove('hidden');
+        this.classList.add('hidden');
+    });
+}
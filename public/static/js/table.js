@@ -0,0 +1,29 @@
+document.getElementById('table-select').addEventListener('change', function () {
+    this.form.submit();
+});
+
+const table = document.getElementById('vault-table');
+
+async function reveal(el) {
+    const response = await fetch('/reveal', {
+        method: 'POST',
+        body: new URLSearchParams({
+            _csrf: table.dataset.csrfToken,
+            table_id: table.dataset.tableId,
+            row_id: el.dataset.row,
+            column: el.dataset.column,
+        }),
+    });
+    if (response.ok) {
+        el.innerText = await response.text();
+        el.title = '';
+        el.classList.remove('hidden-cell');
+    } else {
+        //The password confirmation has most likely expired, reloading shows the confirmation links
+        window.location.reload();
+    }
+}
+
+for (const el of document.querySelectorAll('.hidden-cell[data-column]')) {
+    el.addEventListener('click', () => reveal(el), {once: true});
+}
@@ -0,0 +1,368 @@
+//! Contains the types of the tamper-evident audit log
+//!
+//! Every entry contains the hash of its own content and the hash of the previous entry.
+//! Changing or removing an entry therefore breaks the chain, which is detected by [`verify`].
+//! The hashes are HMACs keyed with `audit_log_key` of the config, so the chain can't be recomputed with access to the
+//! database alone.
+
+use crate::database::AuditLogEntry;
+use sha2::Digest;
+
+/// An action that is recorded in the audit log
+#[derive(Debug, Copy, Clone, PartialEq, rocket::FromFormField)]
+pub enum AuditAction {
+    Login,
+    Logout,
+    #[field(value = "change_password")]
+    ChangePassword,
+    #[field(value = "confirm_password")]
+    ConfirmPassword,
+    #[field(value = "create_account")]
+    CreateAccount,
+    #[field(value = "edit_account")]
+    EditAccount,
+    #[field(value = "delete_account")]
+    DeleteAccount,
+    #[field(value = "create_role")]
+    CreateRole,
+    #[field(value = "update_role")]
+    UpdateRole,
+    #[field(value = "delete_role")]
+    DeleteRole,
+    #[field(value = "assign_role")]
+    AssignRole,
+    #[field(value = "unassign_role")]
+    UnassignRole,
+    #[field(value = "create_group")]
+    CreateGroup,
+    #[field(value = "rename_group")]
+    RenameGroup,
+    #[field(value = "delete_group")]
+    DeleteGroup,
+    #[field(value = "add_group_member")]
+    AddGroupMember,
+    #[field(value = "remove_group_member")]
+    RemoveGroupMember,
+    #[field(value = "create_table")]
+    CreateTable,
+    #[field(value = "delete_table")]
+    DeleteTable,
+    #[field(value = "grant_access")]
+    GrantAccess,
+    #[field(value = "revoke_access")]
+    RevokeAccess,
+    #[field(value = "add_row")]
+    AddRow,
+    #[field(value = "update_row")]
+    UpdateRow,
+    #[field(value = "delete_row")]
+    DeleteRow,
+    Reveal,
+    Import,
+    Export,
+    #[field(value = "create_access_token")]
+    CreateAccessToken,
+    #[field(value = "revoke_access_token")]
+    RevokeAccessToken,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 29] = [
+        AuditAction::Login,
+        AuditAction::Logout,
+        AuditAction::ChangePassword,
+        AuditAction::ConfirmPassword,
+        AuditAction::CreateAccount,
+        AuditAction::EditAccount,
+        AuditAction::DeleteAccount,
+        AuditAction::CreateRole,
+        AuditAction::UpdateRole,
+        AuditAction::DeleteRole,
+        AuditAction::AssignRole,
+        AuditAction::UnassignRole,
+        AuditAction::CreateGroup,
+        AuditAction::RenameGroup,
+        AuditAction::DeleteGroup,
+        AuditAction::AddGroupMember,
+        AuditAction::RemoveGroupMember,
+        AuditAction::CreateTable,
+        AuditAction::DeleteTable,
+        AuditAction::GrantAccess,
+        AuditAction::RevokeAccess,
+        AuditAction::AddRow,
+        AuditAction::UpdateRow,
+        AuditAction::DeleteRow,
+        AuditAction::Reveal,
+        AuditAction::Import,
+        AuditAction::Export,
+        AuditAction::CreateAccessToken,
+        AuditAction::RevokeAccessToken,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::Logout => "logout",
+            AuditAction::ChangePassword => "change_password",
+            AuditAction::ConfirmPassword => "confirm_password",
+            AuditAction::CreateAccount => "create_account",
+            AuditAction::EditAccount => "edit_account",
+            AuditAction::DeleteAccount => "delete_account",
+            AuditAction::CreateRole => "create_role",
+            AuditAction::UpdateRole => "update_role",
+            AuditAction::DeleteRole => "delete_role",
+            AuditAction::AssignRole => "assign_role",
+            AuditAction::UnassignRole => "unassign_role",
+            AuditAction::CreateGroup => "create_group",
+            AuditAction::RenameGroup => "rename_group",
+            AuditAction::DeleteGroup => "delete_group",
+            AuditAction::AddGroupMember => "add_group_member",
+            AuditAction::RemoveGroupMember => "remove_group_member",
+            AuditAction::CreateTable => "create_table",
+            AuditAction::DeleteTable => "delete_table",
+            AuditAction::GrantAccess => "grant_access",
+            AuditAction::RevokeAccess => "revoke_access",
+            AuditAction::AddRow => "add_row",
+            AuditAction::UpdateRow => "update_row",
+            AuditAction::DeleteRow => "delete_row",
+            AuditAction::Reveal => "reveal",
+            AuditAction::Import => "import",
+            AuditAction::Export => "export",
+            AuditAction::CreateAccessToken => "create_access_token",
+            AuditAction::RevokeAccessToken => "revoke_access_token",
+        }
+    }
+}
+
+/// A new entry for the audit log, the account that performed the action is added when the entry is written
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub action: AuditAction,
+    pub table_id: Option<u64>,
+    pub row_id: Option<u64>,
+    pub column_name: Option<String>,
+    pub details: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            table_id: None,
+            row_id: None,
+            column_name: None,
+            details: None,
+        }
+    }
+
+    pub fn with_table(mut self, table_id: u64) -> Self {
+        self.table_id = Some(table_id);
+        self
+    }
+
+    pub fn with_row(mut self, row_id: u64) -> Self {
+        self.row_id = Some(row_id);
+        self
+    }
+
+    pub fn with_column(mut self, column_name: &str) -> Self {
+        self.column_name = Some(column_name.to_string());
+        self
+    }
+
+    pub fn with_details<S: Into<String>>(mut self, details: S) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+/// Calculates the keyed hash of an entry, which covers all of its fields and the hash of the previous entry
+#[allow(clippy::too_many_arguments)]
+pub fn hash_entry(
+    key: &[u8],
+    previous_hash: &str,
+    created: &chrono::DateTime<chrono::Utc>,
+    auth_id: u64,
+    action: &str,
+    table_id: Option<u64>,
+    row_id: Option<u64>,
+    column_name: Option<&str>,
+    details: Option<&str>,
+) -> String {
+    hex::encode(hmac_sha256(key, |hasher| {
+        //Every field is prefixed with its length, so that no two different entries result in the same input
+        let mut update = |field: Option<&[u8]>| match field {
+            Some(bytes) => {
+                hasher.update((bytes.len() as u64).to_le_bytes());
+                hasher.update(bytes);
+            }
+            None => hasher.update(u64::MAX.to_le_bytes()),
+        };
+        update(Some(previous_hash.as_bytes()));
+        update(Some(&created.timestamp().to_le_bytes()));
+        update(Some(&auth_id.to_le_bytes()));
+        update(Some(action.as_bytes()));
+        update(table_id.map(u64::to_le_bytes).as_ref().map(|b| &b[..]));
+        update(row_id.map(u64::to_le_bytes).as_ref().map(|b| &b[..]));
+        update(column_name.map(str::as_bytes));
+        update(details.map(str::as_bytes));
+    }))
+}
+
+/// Calculates the HMAC-SHA256 (RFC 2104) of the message that `message` writes into the hasher
+fn hmac_sha256<F: FnOnce(&mut sha2::Sha256)>(key: &[u8], message: F) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&sha2::Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let mut inner = sha2::Sha256::new();
+    inner.update(pad(0x36));
+    message(&mut inner);
+    let mut outer = sha2::Sha256::new();
+    outer.update(pad(0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().to_vec()
+}
+
+/// Checks the hash chain of the given entries, which have to be ordered by their id
+///
+/// Returns the id of the first entry that was changed, removed or inserted afterwards. Entries without a hash are
+/// reported as well.
+pub fn verify(key: &[u8], entries: &[AuditLogEntry]) -> Result<(), u64> {
+    let mut previous_hash = String::new();
+    for entry in entries {
+        let hash = hash_entry(
+            key,
+            &previous_hash,
+            &entry.created,
+            entry.auth_id,
+            &entry.action,
+            entry.table_id,
+            entry.row_id,
+            entry.column_name.as_deref(),
+            entry.details.as_deref(),
+        );
+        if hash != entry.hash {
+            return Err(entry.id);
+        }
+        previous_hash = hash;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_entry, hmac_sha256, verify};
+    use crate::database::AuditLogEntry;
+
+    const KEY: &[u8] = b"audit log key";
+
+    fn entry(id: u64, details: Option<&str>) -> AuditLogEntry {
+        AuditLogEntry {
+            id,
+            created: chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000 + id as i64, 0)
+                .unwrap(),
+            auth_id: 1,
+            name: Some("admin".to_string()),
+            action: "login".to_string(),
+            table_id: None,
+            row_id: Some(id),
+            column_name: None,
+            details: details.map(str::to_string),
+            hash: String::new(),
+        }
+    }
+
+    /// Returns entries with the given ids, chained just like the database does
+    fn chain(ids: &[u64]) -> Vec<AuditLogEntry> {
+        let mut previous_hash = String::new();
+        ids.iter()
+            .map(|id| {
+                let mut entry = entry(*id, Some("via api"));
+                entry.hash = hash_entry(
+                    KEY,
+                    &previous_hash,
+                    &entry.created,
+                    entry.auth_id,
+                    &entry.action,
+                    entry.table_id,
+                    entry.row_id,
+                    entry.column_name.as_deref(),
+                    entry.details.as_deref(),
+                );
+                previous_hash = entry.hash.clone();
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn accepts_an_intact_chain() {
+        assert_eq!(verify(KEY, &[]), Ok(()));
+        assert_eq!(verify(KEY, &chain(&[1, 2, 3])), Ok(()));
+    }
+
+    #[test]
+    fn detects_changed_entries() {
+        let mut entries = chain(&[1, 2, 3]);
+        entries[1].details = Some("via website".to_string());
+        assert_eq!(verify(KEY, &entries), Err(2));
+        let mut entries = chain(&[1, 2, 3]);
+        entries[2].auth_id = 2;
+        assert_eq!(verify(KEY, &entries), Err(3));
+    }
+
+    #[test]
+    fn detects_removed_and_inserted_entries() {
+        let mut entries = chain(&[1, 2, 3]);
+        entries.remove(1);
+        assert_eq!(verify(KEY, &entries), Err(3));
+        let mut entries = chain(&[1, 3]);
+        entries.insert(1, chain(&[2]).remove(0));
+        assert_eq!(verify(KEY, &entries), Err(2));
+    }
+
+    #[test]
+    fn detects_entries_without_a_hash() {
+        let mut entries = chain(&[1, 2, 3]);
+        entries[0].hash = String::new();
+        assert_eq!(verify(KEY, &entries), Err(1));
+    }
+
+    #[test]
+    fn requires_the_key() {
+        let entries = chain(&[1, 2, 3]);
+        assert_eq!(verify(b"another key", &entries), Err(1));
+    }
+
+    #[test]
+    fn calculates_hmacs() {
+        //Test case 2 of RFC 4231
+        let hmac = hmac_sha256(b"Jefe", |h| {
+            sha2::Digest::update(h, b"what do ya want for nothing?")
+        });
+        assert_eq!(
+            hex::encode(hmac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        //Test case 6, the key is longer than a block
+        let hmac = hmac_sha256(&[0xaa; 131], |h| {
+            sha2::Digest::update(h, b"Test Using Larger Than Block-Size Key - Hash Key First")
+        });
+        assert_eq!(
+            hex::encode(hmac),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn distinguishes_missing_and_empty_fields() {
+        let created = chrono::Utc::now();
+        let hash = |details| hash_entry(KEY, "", &created, 1, "login", None, None, None, details);
+        assert_ne!(hash(None), hash(Some("")));
+    }
+}
@@ -0,0 +1,204 @@
+//! Contains the authentication providers that check the credentials of a login
+//!
+//! The providers are tried in order until one of them knows the given credentials.
+//! Local accounts are checked by the [`DatabaseProvider`], accounts of a directory service are provisioned in the `auth`
+//! table on their first login, marked with the name of their provider.
+//! Accounts of an OpenID Connect provider don't log in with a password, see [`oidc`].
+
+use crate::database::{Password, VaultDb};
+use crate::VaultConfig;
+use rocket::fairing;
+use std::fmt;
+
+pub mod ldap;
+pub mod oidc;
+
+/// The provider name of local accounts
+pub const DATABASE_PROVIDER: &str = "database";
+
+#[derive(Debug)]
+pub enum AuthError {
+    Database(sqlx::Error),
+    Ldap(ldap3::LdapError),
+    /// The login at the OpenID Connect provider failed
+    Oidc(String),
+    /// A local account with the same name already exists
+    NameTaken(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Database(e) => write!(f, "database error: {}", e),
+            AuthError::Ldap(e) => write!(f, "ldap error: {}", e),
+            AuthError::Oidc(e) => write!(f, "openid connect error: {}", e),
+            AuthError::NameTaken(name) => write!(f, "the account name {} is already taken", name),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        AuthError::Database(e)
+    }
+}
+
+impl From<ldap3::LdapError> for AuthError {
+    fn from(e: ldap3::LdapError) -> Self {
+        AuthError::Ldap(e)
+    }
+}
+
+impl From<reqwest::Error> for AuthError {
+    fn from(e: reqwest::Error) -> Self {
+        AuthError::Oidc(e.to_string())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AuthError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        AuthError::Oidc(e.to_string())
+    }
+}
+
+#[rocket::async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// The name that is stored with every account of this provider
+    fn name(&self) -> &'static str;
+
+    /// Whether the provider identifies accounts by their name
+    fn requires_username(&self) -> bool;
+
+    /// Returns the account that belongs to the given credentials, `None` if the credentials are wrong
+    async fn authenticate(
+        &self,
+        database: &VaultDb,
+        username: Option<&str>,
+        password: &str,
+    ) -> Result<Option<Password>, AuthError>;
+}
+
+/// Returns the account of an external provider with the given name and creates it if it doesn't exist yet
+///
+/// The admin flag of the account is kept in sync with the provider.
+pub async fn provision_account(
+    database: &VaultDb,
+    username: &str,
+    provider: &str,
+    admin: bool,
+) -> Result<Option<Password>, AuthError> {
+    match database.fetch_external_account(username, provider).await? {
+        Some(mut account) => {
+            if account.admin != admin {
+                database.update_admin(account.id, admin).await?;
+                account.admin = admin;
+            }
+            Ok(Some(account))
+        }
+        None => {
+            match database
+                .insert_external_account(username, provider, admin)
+                .await
+            {
+                Ok(_) => {}
+                Err(sqlx::Error::Database(_)) => {
+                    return Err(AuthError::NameTaken(username.to_string()))
+                }
+                Err(e) => return Err(e.into()),
+            }
+            Ok(database.fetch_external_account(username, provider).await?)
+        }
+    }
+}
+
+/// Checks the passwords of local accounts, which are identified by their password alone
+pub struct DatabaseProvider;
+
+#[rocket::async_trait]
+impl AuthProvider for DatabaseProvider {
+    fn name(&self) -> &'static str {
+        DATABASE_PROVIDER
+    }
+
+    fn requires_username(&self) -> bool {
+        false
+    }
+
+    async fn authenticate(
+        &self,
+        database: &VaultDb,
+        username: Option<&str>,
+        password: &str,
+    ) -> Result<Option<Password>, AuthError> {
+        Ok(database
+            .fetch_password(password)
+            .await?
+            .filter(|p| username.is_none() || username == Some(p.name.as_str())))
+    }
+}
+
+/// Tries all configured providers in order
+pub struct Authenticator(Vec<Box<dyn AuthProvider>>);
+
+impl Authenticator {
+    pub fn fairing() -> impl fairing::Fairing {
+        fairing::AdHoc::on_ignite("Authentication Providers", |rocket| async {
+            let mut providers: Vec<Box<dyn AuthProvider>> = vec![];
+            if let Some(config) = rocket.state::<VaultConfig>().and_then(|c| c.ldap.clone()) {
+                providers.push(Box::new(ldap::LdapProvider::new(config)));
+            }
+            providers.push(Box::new(DatabaseProvider));
+            rocket.manage(Authenticator(providers))
+        })
+    }
+
+    /// Whether the login form has to ask for a username
+    pub fn requires_username(&self) -> bool {
+        self.0.iter().any(|p| p.requires_username())
+    }
+
+    /// Returns the account that belongs to the given credentials
+    ///
+    /// A provider that fails is skipped, so that local accounts can still log in if a directory service is down.
+    /// The error is only returned if no other provider knows the credentials.
+    pub async fn authenticate(
+        &self,
+        database: &VaultDb,
+        username: Option<&str>,
+        password: &str,
+    ) -> Result<Option<Password>, AuthError> {
+        let mut error = None;
+        for provider in &self.0 {
+            if provider.requires_username() && username.is_none() {
+                continue;
+            }
+            match provider.authenticate(database, username, password).await {
+                Ok(Some(account)) => return Ok(Some(account)),
+                Ok(None) => {}
+                Err(e) => {
+                    rocket::warn!("Authentication provider {} failed: {}", provider.name(), e);
+                    error = Some(e);
+                }
+            }
+        }
+        error.map_or(Ok(None), Err)
+    }
+
+    /// Checks the password of the given account with the provider of the account
+    pub async fn verify(
+        &self,
+        database: &VaultDb,
+        account: &Password,
+        password: &str,
+    ) -> Result<bool, AuthError> {
+        match self.0.iter().find(|p| p.name() == account.provider) {
+            Some(provider) => Ok(matches!(
+                provider
+                    .authenticate(database, Some(&account.name), password)
+                    .await?,
+                Some(p) if p.id == account.id
+            )),
+            None => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Contains the authentication provider that binds to an LDAP directory
+
+use super::{AuthError, AuthProvider};
+use crate::database::{Password, VaultDb};
+use ldap3::{LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+
+/// The provider name of accounts that were provisioned from the LDAP directory
+pub const LDAP_PROVIDER: &str = "ldap";
+
+/// The result code of a bind with wrong credentials
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LdapConfig {
+    /// For example `ldap://localhost:389` or `ldaps://ldap.example.com`
+    pub url: String,
+    #[serde(default)]
+    pub starttls: bool,
+    /// The DN of an account, `{username}` is replaced by the escaped username
+    ///
+    /// If it is not set, the account is searched below `user_base` instead.
+    pub user_dn: Option<String>,
+    pub user_base: Option<String>,
+    /// The filter of the account search, `{username}` is replaced by the escaped username
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    /// The service account that searches the accounts and checks the admin group
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    /// The DN of the group whose members are admins of the vault
+    pub admin_group: Option<String>,
+    /// The attribute of the admin group that contains the DNs of its members
+    #[serde(default = "default_member_attribute")]
+    pub member_attribute: String,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_user_filter() -> String {
+    "(uid={username})".to_string()
+}
+fn default_member_attribute() -> String {
+    "member".to_string()
+}
+fn default_timeout() -> u64 {
+    5
+}
+
+pub struct LdapProvider(LdapConfig);
+
+impl LdapProvider {
+    pub fn new(config: LdapConfig) -> Self {
+        LdapProvider(config)
+    }
+
+    /// Binds as the given account and returns whether it is an admin, `None` if the credentials are wrong
+    async fn bind(&self, username: &str, password: &str) -> Result<Option<bool>, LdapError> {
+        let config = &self.0;
+        let settings = LdapConnSettings::new()
+            .set_starttls(config.starttls)
+            .set_conn_timeout(std::time::Duration::from_secs(config.timeout_secs));
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &config.url).await?;
+        ldap3::drive!(conn);
+
+        if let (Some(dn), Some(password)) = (&config.bind_dn, &config.bind_password) {
+            ldap.simple_bind(dn, password).await?.success()?;
+        }
+        let user_dn = match (&config.user_dn, &config.user_base) {
+            (Some(template), _) => template.replace("{username}", &ldap3::dn_escape(username)),
+            (None, Some(base)) => {
+                let filter = config
+                    .user_filter
+                    .replace("{username}", &ldap3::ldap_escape(username));
+                let (entries, _) = ldap
+                    .search(base, Scope::Subtree, &filter, vec!["1.1"])
+                    .await?
+                    .success()?;
+                match entries.into_iter().next() {
+                    Some(entry) => SearchEntry::construct(entry).dn,
+                    None => return Ok(None),
+                }
+            }
+            (None, None) => {
+                rocket::error!("Either ldap.user_dn or ldap.user_base has to be configured");
+                return Ok(None);
+            }
+        };
+
+        match ldap.simple_bind(&user_dn, password).await?.success() {
+            Ok(_) => {}
+            Err(LdapError::LdapResult { result }) if result.rc == INVALID_CREDENTIALS => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+        //The group is checked by the service account, because accounts usually can't read all groups
+        if let (Some(dn), Some(password)) = (&config.bind_dn, &config.bind_password) {
+            ldap.simple_bind(dn, password).await?.success()?;
+        }
+        let admin = match &config.admin_group {
+            Some(group) => {
+                let filter = format!(
+                    "({}={})",
+                    config.member_attribute,
+                    ldap3::ldap_escape(&user_dn)
+                );
+                let (entries, _) = ldap
+                    .search(group, Scope::Base, &filter, vec!["1.1"])
+                    .await?
+                    .success()?;
+                !entries.is_empty()
+            }
+            None => false,
+        };
+        ldap.unbind().await?;
+        Ok(Some(admin))
+    }
+}
+
+#[rocket::async_trait]
+impl AuthProvider for LdapProvider {
+    fn name(&self) -> &'static str {
+        LDAP_PROVIDER
+    }
+
+    fn requires_username(&self) -> bool {
+        true
+    }
+
+    async fn authenticate(
+        &self,
+        database: &VaultDb,
+        username: Option<&str>,
+        password: &str,
+    ) -> Result<Option<Password>, AuthError> {
+        //An empty password would result in an unauthenticated bind, which always succeeds
+        let username = match username {
+            Some(username) if !username.is_empty() && !password.is_empty() => username,
+            _ => return Ok(None),
+        };
+        let admin = match self.bind(username, password).await? {
+            Some(admin) => admin,
+            None => return Ok(None),
+        };
+        super::provision_account(database, username, LDAP_PROVIDER, admin).await
+    }
+}
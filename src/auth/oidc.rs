@@ -0,0 +1,370 @@
+//! Contains the client of an OpenID Connect provider, which is used for single sign-on
+//!
+//! Logins use the authorization code flow with PKCE. The ID token of the provider is validated with the keys that the
+//! provider publishes, its claims are then mapped to an account of the vault.
+
+use super::AuthError;
+use crate::database::{Password, VaultDb};
+use crate::VaultConfig;
+use jsonwebtoken::{jwk, Algorithm, DecodingKey, Validation};
+use rand::Rng;
+use rocket::fairing;
+use rocket::serde::json::Value;
+use rocket::tokio::sync;
+use sha2::Digest;
+use std::{collections, time};
+
+/// The provider name of accounts that were provisioned by the OpenID Connect provider
+pub const OIDC_PROVIDER: &str = "oidc";
+
+/// How long a started login can be completed
+const PENDING_LOGIN_VALIDITY: time::Duration = time::Duration::from_secs(600);
+
+/// How far the clock of the provider may be behind when the `auth_time` claim is checked
+const AUTH_TIME_SKEW: time::Duration = time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct OidcConfig {
+    /// For example `https://accounts.example.com/realms/vault`, the provider metadata is discovered from it
+    pub issuer: String,
+    pub client_id: String,
+    /// Not needed for public clients
+    pub client_secret: Option<String>,
+    /// The URL of the `/oidc/callback` route, as it is registered at the provider
+    pub redirect_url: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// The claim that contains the name of the account
+    #[serde(default = "default_username_claim")]
+    pub username_claim: String,
+    /// The claim that is checked for `admin_value`, either a string or a list of strings
+    #[serde(default = "default_admin_claim")]
+    pub admin_claim: String,
+    /// Accounts whose `admin_claim` contains this value are admins of the vault
+    pub admin_value: Option<String>,
+    /// The name of the provider on the login page
+    #[serde(default = "default_display_name")]
+    pub display_name: String,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "profile".to_string(),
+        "email".to_string(),
+    ]
+}
+fn default_username_claim() -> String {
+    "preferred_username".to_string()
+}
+fn default_admin_claim() -> String {
+    "groups".to_string()
+}
+fn default_display_name() -> String {
+    "Single sign-on".to_string()
+}
+fn default_timeout() -> u64 {
+    10
+}
+
+/// The parts of the provider metadata that are needed for the login
+#[derive(serde::Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    next: Option<String>,
+    /// When a reauthentication was started, the provider has to authenticate the account after that
+    reauthenticate_since: Option<time::SystemTime>,
+    expires: time::Instant,
+}
+
+/// A login that was started at the provider
+pub struct LoginRequest {
+    /// Binds the login to the browser that started it
+    pub state: String,
+    /// The authorization URL of the provider
+    pub url: String,
+}
+
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    metadata: sync::OnceCell<ProviderMetadata>,
+    pending: sync::Mutex<collections::HashMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    pub fn fairing() -> impl fairing::Fairing {
+        fairing::AdHoc::try_on_ignite("OpenID Connect", |rocket| async {
+            let config = match rocket.state::<VaultConfig>().and_then(|c| c.oidc.clone()) {
+                Some(config) => config,
+                None => return Ok(rocket),
+            };
+            match reqwest::Client::builder()
+                .timeout(time::Duration::from_secs(config.timeout_secs))
+                .build()
+            {
+                Ok(http) => Ok(rocket.manage(OidcClient {
+                    config,
+                    http,
+                    metadata: sync::OnceCell::new(),
+                    pending: sync::Mutex::new(collections::HashMap::new()),
+                })),
+                Err(e) => {
+                    rocket::error!("Failed to create the OpenID Connect client: {}", e);
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.config.display_name
+    }
+
+    /// Returns the provider metadata, which is only discovered once
+    async fn metadata(&self) -> Result<&ProviderMetadata, AuthError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                Ok(self
+                    .http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?)
+            })
+            .await
+    }
+
+    /// Starts a new login, which leads to `next` after it is completed
+    ///
+    /// If `reauthenticate` is set, the provider is asked to check the credentials again, even if it still knows
+    /// the account.
+    pub async fn begin_login(
+        &self,
+        next: Option<String>,
+        reauthenticate: bool,
+    ) -> Result<LoginRequest, AuthError> {
+        let metadata = self.metadata().await?;
+        let state = gen_random_string(32);
+        let nonce = gen_random_string(32);
+        let verifier = gen_random_string(64);
+        let challenge = base64::encode_config(
+            sha2::Sha256::digest(verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+        let scope = self.config.scopes.join(" ");
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", &self.config.redirect_url),
+            ("scope", &scope),
+            ("state", &state),
+            ("nonce", &nonce),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        if reauthenticate {
+            params.push(("prompt", "login"));
+            params.push(("max_age", "0"));
+        }
+        let url = reqwest::Url::parse_with_params(&metadata.authorization_endpoint, &params)
+            .map_err(|e| AuthError::Oidc(e.to_string()))?;
+
+        let mut pending = self.pending.lock().await;
+        let now = time::Instant::now();
+        pending.retain(|_, login| login.expires > now);
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce,
+                verifier,
+                next,
+                reauthenticate_since: reauthenticate.then(time::SystemTime::now),
+                expires: now + PENDING_LOGIN_VALIDITY,
+            },
+        );
+        Ok(LoginRequest {
+            state,
+            url: url.to_string(),
+        })
+    }
+
+    /// Completes the login with the given state and returns its account, the page it leads to and whether the
+    /// provider authenticated the account again
+    pub async fn finish_login(
+        &self,
+        database: &VaultDb,
+        state: &str,
+        code: &str,
+    ) -> Result<(Password, Option<String>, bool), AuthError> {
+        let login = match self.pending.lock().await.remove(state) {
+            Some(login) if login.expires > time::Instant::now() => login,
+            _ => return Err(AuthError::Oidc("unknown or expired login".to_string())),
+        };
+        let metadata = self.metadata().await?;
+
+        let mut request = self.http.post(&metadata.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_url),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &login.verifier),
+        ]);
+        if let Some(secret) = &self.config.client_secret {
+            request = request.basic_auth(&self.config.client_id, Some(secret));
+        }
+        let response: TokenResponse = request.send().await?.error_for_status()?.json().await?;
+
+        let claims = self.validate_id_token(metadata, &response.id_token).await?;
+        if claims.get("nonce").and_then(Value::as_str) != Some(login.nonce.as_str()) {
+            return Err(AuthError::Oidc("the nonce doesn't match".to_string()));
+        }
+        if let Some(since) = login.reauthenticate_since {
+            check_auth_time(&claims, since)?;
+        }
+        let username = match claims
+            .get(&self.config.username_claim)
+            .and_then(Value::as_str)
+        {
+            Some(username) if !username.is_empty() => username,
+            _ => {
+                return Err(AuthError::Oidc(format!(
+                    "the claim {} is missing",
+                    self.config.username_claim
+                )))
+            }
+        };
+        let admin = match (
+            &self.config.admin_value,
+            claims.get(&self.config.admin_claim),
+        ) {
+            (Some(value), Some(Value::String(claim))) => claim == value,
+            (Some(value), Some(Value::Array(claim))) => {
+                claim.iter().any(|c| c.as_str() == Some(value.as_str()))
+            }
+            _ => false,
+        };
+        match super::provision_account(database, username, OIDC_PROVIDER, admin).await? {
+            Some(account) => Ok((account, login.next, login.reauthenticate_since.is_some())),
+            None => Err(AuthError::Oidc(format!(
+                "the account {} couldn't be provisioned",
+                username
+            ))),
+        }
+    }
+
+    /// Checks the signature, issuer, audience and expiry of the ID token and returns its claims
+    async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        token: &str,
+    ) -> Result<collections::HashMap<String, Value>, AuthError> {
+        let header = jsonwebtoken::decode_header(token)?;
+        //The client secret must not be accepted as a key, the token has to be signed by the provider
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(AuthError::Oidc(format!(
+                "the algorithm {:?} is not supported",
+                header.alg
+            )));
+        }
+        let keys: jwk::JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let key = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| AuthError::Oidc("the signing key is unknown".to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+        Ok(jsonwebtoken::decode(token, &DecodingKey::from_jwk(key)?, &validation)?.claims)
+    }
+}
+
+/// Checks that the provider authenticated the account after `since`
+///
+/// Providers may ignore `prompt=login` and `max_age=0` and return the existing login instead, so the claim
+/// `auth_time` is required.
+fn check_auth_time(
+    claims: &collections::HashMap<String, Value>,
+    since: time::SystemTime,
+) -> Result<(), AuthError> {
+    let auth_time = claims
+        .get("auth_time")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| AuthError::Oidc("the claim auth_time is missing".to_string()))?;
+    let auth_time = time::UNIX_EPOCH + time::Duration::from_secs(auth_time);
+    if auth_time + AUTH_TIME_SKEW < since {
+        return Err(AuthError::Oidc(
+            "the provider didn't authenticate the account again".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn gen_random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(auth_time: Option<u64>) -> collections::HashMap<String, Value> {
+        auth_time
+            .map(|t| ("auth_time".to_string(), Value::from(t)))
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn auth_time_after_reauthentication() {
+        let since = time::UNIX_EPOCH + time::Duration::from_secs(1_000_000);
+        assert!(check_auth_time(&claims(Some(1_000_005)), since).is_ok());
+        //The clock of the provider may be slightly behind
+        assert!(check_auth_time(&claims(Some(999_950)), since).is_ok());
+    }
+
+    #[test]
+    fn auth_time_before_reauthentication() {
+        let since = time::UNIX_EPOCH + time::Duration::from_secs(1_000_000);
+        assert!(check_auth_time(&claims(Some(999_000)), since).is_err());
+        assert!(check_auth_time(&claims(None), since).is_err());
+    }
+}
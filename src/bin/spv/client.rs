@@ -0,0 +1,287 @@
+//! Contains the client of the JSON API of the vault
+
+use reqwest::blocking;
+use reqwest::{Method, StatusCode};
+use std::{collections, fmt};
+
+/// The cells of a row, mapped from the column name to the plain value
+pub type RowData = collections::HashMap<String, String>;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct TableIndexEntry {
+    pub id: u64,
+    pub ui_name: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ColumnIndexEntry {
+    pub column_name: String,
+    pub ui_name: String,
+    pub required: bool,
+    pub encrypted: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct VaultTable {
+    pub id: u64,
+    pub name: String,
+    pub columns: Vec<ColumnIndexEntry>,
+    pub rows: Vec<TableRow>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct TableRow {
+    pub id: u64,
+    pub cells: Vec<TableCell>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct TableCell {
+    pub column: String,
+    pub data: String,
+    pub encrypted: bool,
+}
+
+/// The name of the key column of every table
+pub const KEY_COLUMN: &str = "key_";
+/// The name of the password column of every table
+pub const PASSWORD_COLUMN: &str = "password";
+
+impl VaultTable {
+    /// Returns the column with the given name, either the name in the API or in the UI
+    pub fn column(&self, name: &str) -> Option<&ColumnIndexEntry> {
+        self.columns
+            .iter()
+            .find(|c| c.column_name == name)
+            .or_else(|| {
+                self.columns
+                    .iter()
+                    .find(|c| c.ui_name.eq_ignore_ascii_case(name))
+            })
+    }
+
+    /// Returns the rows whose key is exactly the given key
+    pub fn rows_with_key(&self, key: &str) -> Vec<&TableRow> {
+        self.rows
+            .iter()
+            .filter(|r| r.cell(KEY_COLUMN) == Some(key))
+            .collect()
+    }
+}
+
+impl TableRow {
+    pub fn cell(&self, column: &str) -> Option<&str> {
+        self.cells
+            .iter()
+            .find(|c| c.column == column)
+            .map(|c| c.data.as_str())
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SessionToken {
+    pub token: String,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The vault answered with an error
+    Api {
+        status: StatusCode,
+        message: String,
+    },
+    /// The session has to confirm the password again before the action
+    ConfirmationRequired(String),
+    Http(reqwest::Error),
+    /// An error of the client itself, for example a table that doesn't exist
+    Other(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Api { status, message } => write!(f, "{} ({})", message, status),
+            ClientError::ConfirmationRequired(message) => f.write_str(message),
+            ClientError::Http(e) => write!(f, "The vault couldn't be reached: {}", e),
+            ClientError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ApiError {
+    message: String,
+    /// Only set if the session has to be confirmed again
+    confirm_uri: Option<String>,
+}
+
+pub struct Client {
+    base_url: String,
+    token: Option<String>,
+    http: blocking::Client,
+}
+
+impl Client {
+    /// Creates a client of the vault at the given URL, for example `https://vault.example.com`
+    pub fn new(url: &str, token: Option<String>) -> Result<Self, ClientError> {
+        Ok(Self {
+            base_url: format!("{}/api/v1", url.trim_end_matches('/')),
+            token,
+            http: blocking::Client::builder()
+                .user_agent(concat!("spv/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> blocking::RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends the request and turns error responses into [`ClientError::Api`]
+    fn send(request: blocking::RequestBuilder) -> Result<blocking::Response, ClientError> {
+        let response = request.send()?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            match response.json::<ApiError>() {
+                Ok(ApiError {
+                    message,
+                    confirm_uri: Some(_),
+                }) => Err(ClientError::ConfirmationRequired(message)),
+                Ok(ApiError { message, .. }) => Err(ClientError::Api { status, message }),
+                Err(_) => Err(ClientError::Api {
+                    status,
+                    message: "The vault answered with an error".to_string(),
+                }),
+            }
+        }
+    }
+
+    /// Sends the request of a sensitive action
+    ///
+    /// Sessions only count as confirmed for a while after the login, afterwards the password is asked for once to
+    /// confirm the session again. Without a terminal, the error is returned instead.
+    fn send_confirmed(
+        &self,
+        request: impl Fn() -> blocking::RequestBuilder,
+    ) -> Result<blocking::Response, ClientError> {
+        match Self::send(request()) {
+            Err(ClientError::ConfirmationRequired(message)) => {
+                let password = rpassword::prompt_password("Password to confirm the session: ")
+                    .map_err(|_| ClientError::ConfirmationRequired(message))?;
+                self.confirm_session(&password)?;
+                Self::send(request())
+            }
+            result => result,
+        }
+    }
+
+    /// Logs in and returns the token of the new session
+    pub fn create_session(
+        &self,
+        username: Option<&str>,
+        password: &str,
+    ) -> Result<SessionToken, ClientError> {
+        let body = serde_json::json!({ "username": username, "password": password });
+        Ok(Self::send(self.request(Method::POST, "/session").json(&body))?.json()?)
+    }
+
+    /// Confirms the password of the session, which allows sensitive actions again
+    pub fn confirm_session(&self, password: &str) -> Result<(), ClientError> {
+        let body = serde_json::json!({ "password": password });
+        Self::send(self.request(Method::POST, "/session/confirm").json(&body))?;
+        Ok(())
+    }
+
+    pub fn delete_session(&self) -> Result<(), ClientError> {
+        Self::send(self.request(Method::DELETE, "/session"))?;
+        Ok(())
+    }
+
+    pub fn tables(&self) -> Result<Vec<TableIndexEntry>, ClientError> {
+        Ok(Self::send(self.request(Method::GET, "/tables"))?.json()?)
+    }
+
+    /// Resolves a table by its id or its name
+    pub fn find_table(&self, table: &str) -> Result<TableIndexEntry, ClientError> {
+        let tables = self.tables()?;
+        let id = table.parse::<u64>().ok();
+        tables
+            .into_iter()
+            .find(|t| Some(t.id) == id || t.ui_name.eq_ignore_ascii_case(table))
+            .ok_or_else(|| ClientError::Other(format!("There is no table {}", table)))
+    }
+
+    /// Fetches a table, only with the rows whose key contains the query if one is given
+    ///
+    /// Decrypting may ask for the password, see [`Client::send_confirmed`].
+    pub fn table(
+        &self,
+        id: u64,
+        query: Option<&str>,
+        decrypt: bool,
+    ) -> Result<VaultTable, ClientError> {
+        let mut params = vec![("decrypt", decrypt.to_string())];
+        if let Some(query) = query {
+            params.push(("q", query.to_string()));
+        }
+        Ok(self
+            .send_confirmed(|| {
+                self.request(Method::GET, &format!("/tables/{}", id))
+                    .query(&params)
+            })?
+            .json()?)
+    }
+
+    pub fn add_row(&self, table_id: u64, row: &RowData) -> Result<TableRow, ClientError> {
+        Ok(Self::send(
+            self.request(Method::POST, &format!("/tables/{}/rows", table_id))
+                .json(row),
+        )?
+        .json()?)
+    }
+
+    /// Updates the given cells of a row, all other cells are left unchanged
+    pub fn update_row(
+        &self,
+        table_id: u64,
+        row_id: u64,
+        row: &RowData,
+    ) -> Result<TableRow, ClientError> {
+        Ok(Self::send(
+            self.request(
+                Method::PUT,
+                &format!("/tables/{}/rows/{}", table_id, row_id),
+            )
+            .json(row),
+        )?
+        .json()?)
+    }
+
+    pub fn delete_row(&self, table_id: u64, row_id: u64) -> Result<(), ClientError> {
+        Self::send(self.request(
+            Method::DELETE,
+            &format!("/tables/{}/rows/{}", table_id, row_id),
+        ))?;
+        Ok(())
+    }
+
+    pub fn search(&self, query: &str) -> Result<Vec<VaultTable>, ClientError> {
+        Ok(Self::send(self.request(Method::GET, "/search").query(&[("q", query)]))?.json()?)
+    }
+}
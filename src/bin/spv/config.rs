@@ -0,0 +1,54 @@
+//! Contains the configuration of the client, which is cached in the user config directory
+
+use std::{fs, io, path};
+
+/// The vault and token the client uses, `SPV_URL` and `SPV_TOKEN` take precedence over it
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ClientConfig {
+    pub url: Option<String>,
+    pub token: Option<String>,
+}
+
+impl ClientConfig {
+    /// Returns `<config dir>/spv/config.json`, for example `~/.config/spv/config.json` on Linux
+    pub fn path() -> Option<path::PathBuf> {
+        dirs::config_dir().map(|d| d.join("spv").join("config.json"))
+    }
+
+    /// Loads the cached configuration, a missing file is an empty configuration
+    pub fn load() -> io::Result<Self> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the configuration, which is only readable by the current user because it contains the token
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "there is no user config directory")
+        })?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_private(&path, &serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Writes a file that is only readable by the current user, an existing file gets the same permissions
+pub fn write_private(path: &path::Path, data: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    io::Write::write_all(&mut file, data)
+}
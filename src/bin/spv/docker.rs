@@ -0,0 +1,261 @@
+//! Contains the docker credential helper, which implements the protocol of `docker-credential-*` helpers
+//!
+//! Docker passes the action as the only argument. `get` and `erase` read the server URL from stdin, `store` reads a
+//! JSON credential. The server URL is the key of a row in the configured table, the username and the secret are
+//! stored in the configured columns.
+
+use crate::client::{Client, RowData, TableRow, VaultTable, KEY_COLUMN};
+use crate::CliResult;
+use std::{collections, error, io};
+
+/// The message docker expects if there is no credential for a server
+pub const NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// Where the credentials are stored in the vault
+pub struct CredentialMapping {
+    /// The name or id of the table
+    pub table: String,
+    /// The column that contains the username
+    pub username_column: String,
+    /// The column that contains the password or identity token
+    pub secret_column: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Credential {
+    #[serde(rename = "ServerURL")]
+    server_url: String,
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: String,
+}
+
+/// Runs the given action of the protocol
+pub fn credential(client: &Client, mapping: &CredentialMapping, action: &str) -> CliResult {
+    match action {
+        "get" => get(client, mapping, &read_server_url(io::stdin().lock())?),
+        "store" => store(client, mapping, read_credential(io::stdin().lock())?),
+        "erase" => erase(client, mapping, &read_server_url(io::stdin().lock())?),
+        "list" => list(client, mapping),
+        _ => Err(format!("Unknown action {}", action).into()),
+    }
+}
+
+fn read_server_url<R: io::Read>(mut input: R) -> Result<String, Box<dyn error::Error>> {
+    let mut server_url = String::new();
+    input.read_to_string(&mut server_url)?;
+    match server_url.trim() {
+        "" => Err("No server URL was given".into()),
+        server_url => Ok(server_url.to_string()),
+    }
+}
+
+fn read_credential<R: io::Read>(input: R) -> Result<Credential, Box<dyn error::Error>> {
+    let credential: Credential = serde_json::from_reader(input)?;
+    if credential.server_url.trim().is_empty() {
+        return Err("No server URL was given".into());
+    }
+    Ok(credential)
+}
+
+impl Credential {
+    fn from_row(
+        server_url: &str,
+        row: &TableRow,
+        username_column: &str,
+        secret_column: &str,
+    ) -> Self {
+        Self {
+            server_url: server_url.to_string(),
+            username: row.cell(username_column).unwrap_or_default().to_string(),
+            secret: row.cell(secret_column).unwrap_or_default().to_string(),
+        }
+    }
+}
+
+impl CredentialMapping {
+    /// Returns the names of the username and the secret column in the API
+    fn columns(&self, table: &VaultTable) -> Result<(String, String), Box<dyn error::Error>> {
+        let column = |name: &str| {
+            table
+                .column(name)
+                .map(|c| c.column_name.clone())
+                .ok_or_else(|| format!("There is no column {} in {}", name, table.name))
+        };
+        Ok((column(&self.username_column)?, column(&self.secret_column)?))
+    }
+}
+
+/// Returns the row of the server, several rows are an error because docker can only use one of them
+fn find_row<'a>(
+    table: &'a VaultTable,
+    server_url: &str,
+) -> Result<Option<&'a TableRow>, Box<dyn error::Error>> {
+    match table.rows_with_key(server_url)[..] {
+        [] => Ok(None),
+        [row] => Ok(Some(row)),
+        _ => Err(format!(
+            "There are several credentials for {} in {}",
+            server_url, table.name
+        )
+        .into()),
+    }
+}
+
+/// Writes the credential of the server
+fn get(client: &Client, mapping: &CredentialMapping, server_url: &str) -> CliResult {
+    let table = crate::fetch_table(client, &mapping.table, Some(server_url), true)?;
+    let (username_column, secret_column) = mapping.columns(&table)?;
+    let row = find_row(&table, server_url)?.ok_or(NOT_FOUND)?;
+    crate::print_json(&Credential::from_row(
+        server_url,
+        row,
+        &username_column,
+        &secret_column,
+    ))?;
+    Ok(())
+}
+
+/// Saves the credential of `docker login`, which replaces the credential of the same server
+fn store(client: &Client, mapping: &CredentialMapping, credential: Credential) -> CliResult {
+    let table = crate::fetch_table(client, &mapping.table, Some(&credential.server_url), false)?;
+    let (username_column, secret_column) = mapping.columns(&table)?;
+    let mut row = RowData::new();
+    row.insert(username_column, credential.username);
+    row.insert(secret_column, credential.secret);
+    match find_row(&table, &credential.server_url)? {
+        Some(existing) => {
+            client.update_row(table.id, existing.id, &row)?;
+        }
+        None => {
+            row.insert(KEY_COLUMN.to_string(), credential.server_url);
+            client.add_row(table.id, &row)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the credential of the server on `docker logout`
+fn erase(client: &Client, mapping: &CredentialMapping, server_url: &str) -> CliResult {
+    let table = crate::fetch_table(client, &mapping.table, Some(server_url), false)?;
+    let row = find_row(&table, server_url)?.ok_or(NOT_FOUND)?;
+    client.delete_row(table.id, row.id)?;
+    Ok(())
+}
+
+/// Writes the usernames of all servers, the table is only decrypted if the username column is encrypted
+fn list(client: &Client, mapping: &CredentialMapping) -> CliResult {
+    let table = crate::fetch_table(client, &mapping.table, None, false)?;
+    let (username_column, _) = mapping.columns(&table)?;
+    let table = match table.column(&username_column) {
+        Some(column) if column.encrypted => client.table(table.id, None, true)?,
+        _ => table,
+    };
+    crate::print_json(&servers(&table, &username_column))?;
+    Ok(())
+}
+
+/// Maps the server URLs to their usernames, rows without a server URL are skipped
+fn servers<'a>(
+    table: &'a VaultTable,
+    username_column: &str,
+) -> collections::BTreeMap<&'a str, &'a str> {
+    table
+        .rows
+        .iter()
+        .filter_map(|r| {
+            let server_url = r.cell(KEY_COLUMN).filter(|k| !k.is_empty())?;
+            Some((server_url, r.cell(username_column).unwrap_or_default()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_row, read_credential, read_server_url, servers, Credential};
+    use crate::client::VaultTable;
+    use serde_json::json;
+
+    fn table() -> VaultTable {
+        serde_json::from_value(json!({
+            "id": 2,
+            "name": "Registries",
+            "columns": [
+                {"column_name": "key_", "ui_name": "Key", "required": true, "encrypted": false},
+                {"column_name": "password", "ui_name": "Password", "required": true, "encrypted": true},
+                {"column_name": "extra_0", "ui_name": "User", "required": false, "encrypted": false}
+            ],
+            "rows": [
+                {"id": 1, "cells": [
+                    {"column": "key_", "data": "https://index.docker.io/v1/", "encrypted": false},
+                    {"column": "password", "data": "s3cret", "encrypted": true},
+                    {"column": "extra_0", "data": "alice", "encrypted": false}
+                ]},
+                {"id": 2, "cells": [
+                    {"column": "key_", "data": "ghcr.io", "encrypted": false},
+                    {"column": "password", "data": "token", "encrypted": true}
+                ]},
+                {"id": 3, "cells": [
+                    {"column": "key_", "data": "", "encrypted": false},
+                    {"column": "extra_0", "data": "bob", "encrypted": false}
+                ]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_the_server_url() {
+        assert_eq!(read_server_url("ghcr.io\n".as_bytes()).unwrap(), "ghcr.io");
+        assert!(read_server_url(" \n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reads_the_credential_to_store() {
+        let input = r#"{"ServerURL": "ghcr.io", "Username": "alice", "Secret": "token"}"#;
+        let credential = read_credential(input.as_bytes()).unwrap();
+        assert_eq!(credential.server_url, "ghcr.io");
+        assert_eq!(credential.username, "alice");
+        assert_eq!(credential.secret, "token");
+        assert!(
+            read_credential(r#"{"ServerURL": "", "Username": "", "Secret": ""}"#.as_bytes())
+                .is_err()
+        );
+        assert!(read_credential(r#"{"ServerURL": "ghcr.io"}"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn writes_the_credential_of_a_row() {
+        let table = table();
+        let row = find_row(&table, "https://index.docker.io/v1/")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(Credential::from_row(
+                "https://index.docker.io/v1/",
+                row,
+                "extra_0",
+                "password"
+            ))
+            .unwrap(),
+            json!({"ServerURL": "https://index.docker.io/v1/", "Username": "alice", "Secret": "s3cret"})
+        );
+        //A missing username is empty, which docker treats as an identity token
+        let row = find_row(&table, "ghcr.io").unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(Credential::from_row("ghcr.io", row, "extra_0", "password"))
+                .unwrap(),
+            json!({"ServerURL": "ghcr.io", "Username": "", "Secret": "token"})
+        );
+        assert!(find_row(&table, "quay.io").unwrap().is_none());
+    }
+
+    #[test]
+    fn lists_the_usernames_of_all_servers() {
+        assert_eq!(
+            serde_json::to_value(servers(&table(), "extra_0")).unwrap(),
+            json!({"https://index.docker.io/v1/": "alice", "ghcr.io": ""})
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Contains `spv run`, which starts a command with values of the vault in its environment
+//!
+//! The values are only passed to the environment of the child process, they are never written to disk.
+
+use crate::client::{Client, PASSWORD_COLUMN};
+use std::{collections, error, process};
+
+/// Maps an environment variable to a cell of a row
+#[derive(Clone)]
+pub struct EnvMapping {
+    variable: String,
+    key: String,
+    column: String,
+}
+
+impl std::str::FromStr for EnvMapping {
+    type Err = String;
+
+    /// Parses `VAR=KEY[:COLUMN]`, the column is the password column by default
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (variable, cell) = s
+            .split_once('=')
+            .filter(|(v, _)| !v.is_empty() && !v.contains('\0'))
+            .ok_or_else(|| format!("{} is not in the form VAR=KEY[:COLUMN]", s))?;
+        let (key, column) = cell.rsplit_once(':').unwrap_or((cell, PASSWORD_COLUMN));
+        Ok(Self {
+            variable: variable.to_string(),
+            key: key.to_string(),
+            column: column.to_string(),
+        })
+    }
+}
+
+/// Fetches the mapped values and runs the command with them, returns the exit code of the command
+pub fn run(
+    client: &Client,
+    table: &str,
+    mappings: &[EnvMapping],
+    command: &[String],
+) -> Result<i32, Box<dyn error::Error>> {
+    let entry = client.find_table(table)?;
+    let mut env = collections::HashMap::new();
+    //Every key is fetched once, so only the needed rows are decrypted
+    let keys: collections::BTreeSet<&str> = mappings.iter().map(|m| m.key.as_str()).collect();
+    for key in keys {
+        let table = client.table(entry.id, Some(key), true)?;
+        let row = crate::find_row(&table, key)?;
+        for mapping in mappings.iter().filter(|m| m.key == key) {
+            let column = table.column(&mapping.column).ok_or_else(|| {
+                format!("There is no column {} in {}", mapping.column, table.name)
+            })?;
+            let value = row.cell(&column.column_name).unwrap_or_default();
+            env.insert(mapping.variable.as_str(), value.to_string());
+        }
+    }
+
+    let (program, args) = command.split_first().ok_or("No command was given")?;
+    let status = process::Command::new(program)
+        .args(args)
+        .envs(env)
+        .status()
+        .map_err(|e| format!("{} couldn't be started: {}", program, e))?;
+    Ok(exit_code(status))
+}
+
+/// Returns the exit code of the command, a command that was killed by a signal exits like a shell reports it
+fn exit_code(status: process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvMapping;
+
+    fn parse(s: &str) -> Result<(String, String, String), String> {
+        s.parse::<EnvMapping>()
+            .map(|m| (m.variable, m.key, m.column))
+    }
+
+    fn mapping(variable: &str, key: &str, column: &str) -> (String, String, String) {
+        (variable.to_string(), key.to_string(), column.to_string())
+    }
+
+    #[test]
+    fn uses_the_password_column_by_default() {
+        assert_eq!(
+            parse("DB_PASSWORD=prod-db"),
+            Ok(mapping("DB_PASSWORD", "prod-db", "password"))
+        );
+    }
+
+    #[test]
+    fn parses_the_column_after_the_last_colon() {
+        assert_eq!(
+            parse("DB_USER=prod-db:User"),
+            Ok(mapping("DB_USER", "prod-db", "User"))
+        );
+        assert_eq!(
+            parse("DB_USER=db:5432:extra_0"),
+            Ok(mapping("DB_USER", "db:5432", "extra_0"))
+        );
+    }
+
+    #[test]
+    fn keeps_equal_signs_in_the_key() {
+        assert_eq!(parse("TOKEN=a=b"), Ok(mapping("TOKEN", "a=b", "password")));
+    }
+
+    #[test]
+    fn rejects_invalid_variables() {
+        assert!(parse("prod-db").is_err());
+        assert!(parse("=prod-db").is_err());
+        assert!(parse("DB\0=prod-db").is_err());
+    }
+}
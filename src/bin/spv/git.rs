@@ -0,0 +1,274 @@
+//! Contains the git credential helper, which implements the protocol of `git credential`
+//!
+//! Git writes the attributes of a credential (`protocol`, `host`, `path`, `username`, ...) as `key=value` lines to
+//! stdin. The key pattern maps them to the key of a row in the configured table, whose password (and optionally
+//! username) is written back to stdout.
+
+use crate::client::{Client, RowData, TableRow, VaultTable, KEY_COLUMN, PASSWORD_COLUMN};
+use crate::CliResult;
+use std::{collections, error, io};
+
+/// Where the credentials are stored in the vault
+pub struct CredentialMapping {
+    /// The name or id of the table
+    pub table: String,
+    /// For example `{host}` or `{username}@{host}`
+    pub key_pattern: String,
+    /// The column that contains the username, if any
+    pub username_column: Option<String>,
+}
+
+type Attributes = collections::HashMap<String, String>;
+
+/// Runs the given action of the protocol, unknown actions are ignored as the protocol requires
+pub fn credential(
+    client: &Client,
+    mapping: &CredentialMapping,
+    action: &str,
+    read_only: bool,
+) -> CliResult {
+    let attributes = read_attributes(io::stdin().lock())?;
+    match action {
+        "get" => get(client, mapping, &attributes),
+        "store" if !read_only => store(client, mapping, &attributes),
+        "erase" if !read_only => erase(client, mapping, &attributes),
+        _ => Ok(()),
+    }
+}
+
+/// Reads `key=value` lines until an empty line or the end of the input
+fn read_attributes<R: io::BufRead>(input: R) -> io::Result<Attributes> {
+    let mut attributes = Attributes::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            attributes.insert(key.to_string(), value.to_string());
+        }
+    }
+    Ok(attributes)
+}
+
+impl CredentialMapping {
+    /// Returns the key of the credential, if the attributes contain all placeholders of the pattern
+    fn key(&self, attributes: &Attributes) -> Option<String> {
+        let mut key = self.key_pattern.clone();
+        for name in ["protocol", "host", "path", "username"] {
+            let placeholder = format!("{{{}}}", name);
+            if key.contains(&placeholder) {
+                key = key.replace(&placeholder, attributes.get(name)?);
+            }
+        }
+        Some(key)
+    }
+
+    /// Whether the username is part of the key, otherwise it is looked up in the username column
+    fn key_contains_username(&self) -> bool {
+        self.key_pattern.contains("{username}")
+    }
+
+    /// Returns the row of the credential, rows of other usernames are skipped if there is a username column
+    fn find<'a>(
+        &self,
+        table: &'a VaultTable,
+        key: &str,
+        attributes: &Attributes,
+    ) -> Result<Option<&'a TableRow>, Box<dyn error::Error>> {
+        let username = attributes
+            .get("username")
+            .filter(|_| !self.key_contains_username());
+        let username_column = self.username_column(table)?;
+        let rows: Vec<&TableRow> = table
+            .rows_with_key(key)
+            .into_iter()
+            .filter(|r| match (username, &username_column) {
+                (Some(username), Some(column)) => r.cell(column) == Some(username.as_str()),
+                _ => true,
+            })
+            .collect();
+        match rows[..] {
+            [] => Ok(None),
+            [row] => Ok(Some(row)),
+            _ => Err(format!(
+                "There are several credentials for {} in {}",
+                key, table.name
+            )
+            .into()),
+        }
+    }
+
+    fn username_column(&self, table: &VaultTable) -> Result<Option<String>, Box<dyn error::Error>> {
+        match &self.username_column {
+            Some(name) => match table.column(name) {
+                Some(column) => Ok(Some(column.column_name.clone())),
+                None => Err(format!("There is no column {} in {}", name, table.name).into()),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Writes the username and password of the credential, nothing is written if there is none
+fn get(client: &Client, mapping: &CredentialMapping, attributes: &Attributes) -> CliResult {
+    let key = match mapping.key(attributes) {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let table = crate::fetch_table(client, &mapping.table, Some(&key), true)?;
+    let row = match mapping.find(&table, &key, attributes)? {
+        Some(row) => row,
+        None => return Ok(()),
+    };
+    let username = match mapping.username_column(&table)? {
+        Some(column) => row.cell(&column).filter(|u| !u.is_empty()),
+        None => None,
+    }
+    .or_else(|| attributes.get("username").map(String::as_str));
+    let password = row.cell(PASSWORD_COLUMN).unwrap_or_default();
+    write_credential(&mut io::stdout().lock(), username, password)
+}
+
+/// Writes the attributes of a credential in the format of git
+fn write_credential<W: io::Write>(
+    output: &mut W,
+    username: Option<&str>,
+    password: &str,
+) -> CliResult {
+    //A line break would end the attribute and let the value inject other attributes
+    if username
+        .into_iter()
+        .chain([password])
+        .any(|v| v.contains(['\n', '\0']))
+    {
+        return Err("The credential contains a line break".into());
+    }
+    if let Some(username) = username {
+        writeln!(output, "username={}", username)?;
+    }
+    writeln!(output, "password={}", password)?;
+    Ok(())
+}
+
+/// Saves a credential that git used successfully
+///
+/// Git also stores credentials that came from the vault after every use, so existing credentials are left unchanged. A
+/// changed password replaces the old one, because git erases the rejected credential first.
+fn store(client: &Client, mapping: &CredentialMapping, attributes: &Attributes) -> CliResult {
+    let (key, password) = match (mapping.key(attributes), attributes.get("password")) {
+        (Some(key), Some(password)) => (key, password),
+        _ => return Ok(()),
+    };
+    let table = crate::fetch_table(client, &mapping.table, Some(&key), false)?;
+    if mapping.find(&table, &key, attributes)?.is_some() {
+        return Ok(());
+    }
+    let mut row = RowData::new();
+    row.insert(KEY_COLUMN.to_string(), key);
+    row.insert(PASSWORD_COLUMN.to_string(), password.clone());
+    if let (Some(column), Some(username)) =
+        (mapping.username_column(&table)?, attributes.get("username"))
+    {
+        row.insert(column, username.clone());
+    }
+    client.add_row(table.id, &row)?;
+    Ok(())
+}
+
+/// Removes a credential that git rejected, but only if it still has the rejected password
+fn erase(client: &Client, mapping: &CredentialMapping, attributes: &Attributes) -> CliResult {
+    let (key, password) = match (mapping.key(attributes), attributes.get("password")) {
+        (Some(key), Some(password)) => (key, password),
+        _ => return Ok(()),
+    };
+    let table = crate::fetch_table(client, &mapping.table, Some(&key), true)?;
+    if let Some(row) = mapping.find(&table, &key, attributes)? {
+        if row.cell(PASSWORD_COLUMN) == Some(password.as_str()) {
+            client.delete_row(table.id, row.id)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_attributes, write_credential, Attributes, CredentialMapping};
+
+    fn mapping(key_pattern: &str) -> CredentialMapping {
+        CredentialMapping {
+            table: "Git".to_string(),
+            key_pattern: key_pattern.to_string(),
+            username_column: None,
+        }
+    }
+
+    fn attributes(pairs: &[(&str, &str)]) -> Attributes {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn fills_in_the_key_pattern() {
+        let attributes = attributes(&[
+            ("protocol", "https"),
+            ("host", "git.example.com"),
+            ("username", "alice"),
+        ]);
+        assert_eq!(
+            mapping("{host}").key(&attributes),
+            Some("git.example.com".to_string())
+        );
+        assert_eq!(
+            mapping("{username}@{host}").key(&attributes),
+            Some("alice@git.example.com".to_string())
+        );
+        assert_eq!(
+            mapping("{protocol}://{host}").key(&attributes),
+            Some("https://git.example.com".to_string())
+        );
+        assert_eq!(mapping("git").key(&attributes), Some("git".to_string()));
+    }
+
+    #[test]
+    fn has_no_key_without_all_placeholders() {
+        let attributes = attributes(&[("protocol", "https"), ("host", "git.example.com")]);
+        assert_eq!(mapping("{host}/{path}").key(&attributes), None);
+        assert_eq!(mapping("{username}@{host}").key(&attributes), None);
+    }
+
+    #[test]
+    fn reads_attributes_until_an_empty_line() {
+        let input =
+            "protocol=https\nhost=git.example.com\npassword=a=b\ninvalid\n\nusername=alice\n";
+        assert_eq!(
+            read_attributes(input.as_bytes()).unwrap(),
+            attributes(&[
+                ("protocol", "https"),
+                ("host", "git.example.com"),
+                ("password", "a=b"),
+            ])
+        );
+    }
+
+    #[test]
+    fn writes_credentials() {
+        let mut output = Vec::new();
+        write_credential(&mut output, Some("alice"), "s3cret").unwrap();
+        assert_eq!(output, b"username=alice\npassword=s3cret\n");
+        let mut output = Vec::new();
+        write_credential(&mut output, None, "s3cret").unwrap();
+        assert_eq!(output, b"password=s3cret\n");
+    }
+
+    #[test]
+    fn refuses_to_write_line_breaks() {
+        let mut output = Vec::new();
+        assert!(write_credential(&mut output, None, "s3cret\nhost=evil.example.com").is_err());
+        assert!(write_credential(&mut output, Some("alice\nusername=bob"), "s3cret").is_err());
+        assert!(write_credential(&mut output, None, "s3cret\0").is_err());
+        assert!(output.is_empty());
+    }
+}
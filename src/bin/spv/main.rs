@@ -0,0 +1,434 @@
+//! # spv
+//! The command-line client of the Simple Password Vault, which talks to the JSON API of a vault
+//!
+//! The URL of the vault and the token are cached in the user config directory by `spv login`. Both can be overridden
+//! with the `SPV_URL` and `SPV_TOKEN` environment variables, for example in CI jobs that use a personal access token.
+
+use crate::client::{Client, ClientError, RowData, VaultTable, KEY_COLUMN, PASSWORD_COLUMN};
+use crate::config::ClientConfig;
+use clap::{Parser, Subcommand};
+use std::{error, fs, io, path, process};
+
+mod client;
+mod config;
+mod docker;
+mod exec;
+mod git;
+mod template;
+
+#[derive(Parser)]
+#[command(
+    name = "spv",
+    version,
+    about = "Command-line client of the Simple Password Vault"
+)]
+struct Cli {
+    /// The URL of the vault, for example `https://vault.example.com`
+    #[arg(long, global = true, env = "SPV_URL")]
+    url: Option<String>,
+    /// A session or personal access token, instead of the cached one
+    #[arg(long, global = true, env = "SPV_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Prints JSON instead of plain text
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Logs in and caches the token
+    ///
+    /// The session stays valid for `token_validity_duration_secs` of the vault (a day by default), but it only counts as
+    /// confirmed for `sudo_duration_secs` (15 minutes by default). Afterwards, commands that decrypt values ask for the password again to confirm the session.
+    /// Without a terminal, for example in CI jobs, they fail instead, so use a personal access token that can decrypt there.
+    Login {
+        /// Only needed for accounts of a directory service
+        #[arg(long)]
+        username: Option<String>,
+        /// Asks for a personal access token instead of the password
+        #[arg(long)]
+        access_token: bool,
+    },
+    /// Logs out and removes the cached token
+    Logout,
+    /// Lists all tables
+    Tables,
+    /// Prints one decrypted field of the row with the given key
+    Get {
+        /// The name or id of the table
+        table: String,
+        key: String,
+        /// The column to print
+        #[arg(long, short, default_value = PASSWORD_COLUMN)]
+        field: String,
+    },
+    /// Adds a row, the password is asked for if it isn't set
+    Add {
+        /// The name or id of the table
+        table: String,
+        key: String,
+        /// Sets a column of the new row, can be given multiple times
+        #[arg(long = "set", short = 's', value_name = "COLUMN=VALUE")]
+        cells: Vec<String>,
+    },
+    /// Removes the row with the given key
+    Rm {
+        /// The name or id of the table
+        table: String,
+        key: String,
+    },
+    /// Searches the keys of all tables
+    Search { query: String },
+    /// Adds all rows of a csv file, whose columns are in the same order as the columns of the table
+    Import {
+        /// The name or id of the table
+        table: String,
+        file: path::PathBuf,
+    },
+    /// Writes all rows of a table as csv, with decrypted values
+    Export {
+        /// The name or id of the table
+        table: String,
+        /// The file to write to instead of stdout, which is only readable by the current user
+        #[arg(long, short)]
+        output: Option<path::PathBuf>,
+    },
+    /// Fills in values of the vault in a template, for example `{{ vault "Customers" "acme" "password" }}`
+    Template {
+        file: path::PathBuf,
+        /// The file to write to instead of stdout, which is only readable by the current user
+        #[arg(long, short)]
+        output: Option<path::PathBuf>,
+    },
+    /// Runs a command with values of a table in its environment and exits with its exit code
+    Run {
+        /// The name or id of the table
+        #[arg(long)]
+        table: String,
+        /// Sets the variable to a cell of the row with the given key, the password by default
+        #[arg(long = "map", value_name = "VAR=KEY[:COLUMN]", required = true)]
+        mappings: Vec<exec::EnvMapping>,
+        /// The command and its arguments, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Implements the git credential helper protocol, see `git help credentials`
+    GitCredential {
+        /// The name or id of the table that contains the credentials
+        #[arg(long)]
+        table: String,
+        /// The key of a credential, which can contain `{protocol}`, `{host}`, `{path}` and `{username}`
+        #[arg(long, default_value = "{host}")]
+        key: String,
+        /// The column that contains the username
+        #[arg(long)]
+        username_column: Option<String>,
+        /// Ignores `store` and `erase`, so git never changes the table
+        #[arg(long)]
+        read_only: bool,
+        /// `get`, `store` or `erase`, which is passed by git
+        action: String,
+    },
+    /// Implements the docker credential helper protocol, which is called by a `docker-credential-*` script
+    DockerCredential {
+        /// The name or id of the table that contains the credentials, the server URL is the key
+        #[arg(long)]
+        table: String,
+        /// The column that contains the username
+        #[arg(long)]
+        username_column: String,
+        /// The column that contains the password or identity token
+        #[arg(long, default_value = PASSWORD_COLUMN)]
+        secret_column: String,
+        /// `get`, `store`, `erase` or `list`, which is passed by docker
+        action: String,
+    },
+}
+
+type CliResult = Result<(), Box<dyn error::Error>>;
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> CliResult {
+    let mut config = ClientConfig::load()?;
+    let url = match cli.url.clone().or_else(|| config.url.clone()) {
+        Some(url) => url,
+        None => {
+            return Err(
+                "The URL of the vault is unknown, log in with `spv login --url <URL>`".into(),
+            )
+        }
+    };
+
+    match cli.command {
+        Command::Login {
+            username,
+            access_token,
+        } => {
+            let token = if access_token {
+                let token = rpassword::prompt_password("Access token: ")?;
+                //Makes sure that the token works before it is cached
+                Client::new(&url, Some(token.clone()))?.tables()?;
+                token
+            } else {
+                let password = rpassword::prompt_password("Password: ")?;
+                Client::new(&url, None)?
+                    .create_session(username.as_deref(), &password)?
+                    .token
+            };
+            config.url = Some(url);
+            config.token = Some(token);
+            config.save()?;
+            eprintln!("Logged in");
+            Ok(())
+        }
+        Command::Logout => {
+            //Personal access tokens stay valid, they can only be revoked on the website
+            if let Some(token) = config.token.take().filter(|t| !t.starts_with("spv_")) {
+                if let Err(e) = Client::new(&url, Some(token))?.delete_session() {
+                    eprintln!("The session couldn't be revoked: {}", e);
+                }
+            }
+            config.save()?;
+            eprintln!("Logged out");
+            Ok(())
+        }
+        command => {
+            let token = match cli.token.or(config.token) {
+                Some(token) => token,
+                None => return Err("Not logged in, log in with `spv login`".into()),
+            };
+            run_command(&Client::new(&url, Some(token))?, command, cli.json)
+        }
+    }
+}
+
+/// Runs a command that needs a token
+fn run_command(client: &Client, command: Command, json: bool) -> CliResult {
+    match command {
+        Command::Login { .. } | Command::Logout => {
+            return Err("This command can't be run with a token".into())
+        }
+        Command::Tables => {
+            let tables = client.tables()?;
+            if json {
+                print_json(&tables)?;
+            } else {
+                for table in tables {
+                    println!("{}\t{}", table.id, table.ui_name);
+                }
+            }
+        }
+        Command::Get { table, key, field } => {
+            let table = fetch_table(client, &table, Some(&key), true)?;
+            let column = table
+                .column(&field)
+                .ok_or_else(|| ClientError::Other(format!("There is no column {}", field)))?;
+            let value = find_row(&table, &key)?
+                .cell(&column.column_name)
+                .unwrap_or_default();
+            if json {
+                print_json(
+                    &serde_json::json!({ "key": key, "field": column.ui_name, "value": value }),
+                )?;
+            } else {
+                println!("{}", value);
+            }
+        }
+        Command::Add { table, key, cells } => {
+            let table = fetch_table(client, &table, Some(&key), false)?;
+            let mut row = RowData::new();
+            for cell in cells {
+                let (name, value) = cell
+                    .split_once('=')
+                    .ok_or_else(|| format!("{} is not in the form COLUMN=VALUE", cell))?;
+                let column = table
+                    .column(name)
+                    .ok_or_else(|| ClientError::Other(format!("There is no column {}", name)))?;
+                row.insert(column.column_name.clone(), value.to_string());
+            }
+            row.insert(KEY_COLUMN.to_string(), key);
+            if !row.contains_key(PASSWORD_COLUMN) {
+                row.insert(
+                    PASSWORD_COLUMN.to_string(),
+                    rpassword::prompt_password("Password: ")?,
+                );
+            }
+            let row = client.add_row(table.id, &row)?;
+            if json {
+                print_json(&row)?;
+            } else {
+                eprintln!("Added row {}", row.id);
+            }
+        }
+        Command::Rm { table, key } => {
+            let table = fetch_table(client, &table, Some(&key), false)?;
+            let row = find_row(&table, &key)?;
+            client.delete_row(table.id, row.id)?;
+            eprintln!("Removed row {}", row.id);
+        }
+        Command::Search { query } => {
+            let tables = client.search(&query)?;
+            if json {
+                print_json(&tables)?;
+            } else {
+                for table in tables {
+                    for row in &table.rows {
+                        println!(
+                            "{}\t{}",
+                            table.name,
+                            row.cell(KEY_COLUMN).unwrap_or_default()
+                        );
+                    }
+                }
+            }
+        }
+        Command::Import { table, file } => {
+            let table = fetch_table(client, &table, Some(""), false)?;
+            let mut count = 0;
+            for record in csv::Reader::from_path(file)?.into_records() {
+                let record = record?;
+                if record.len() != table.columns.len() {
+                    return Err(format!(
+                        "Line {} doesn't contain the {} columns of the table",
+                        record.position().map(|p| p.line()).unwrap_or_default(),
+                        table.columns.len()
+                    )
+                    .into());
+                }
+                let row: RowData = table
+                    .columns
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(c, v)| (c.column_name.clone(), v.to_string()))
+                    .collect();
+                client.add_row(table.id, &row)?;
+                count += 1;
+            }
+            if json {
+                print_json(&serde_json::json!({ "rows": count }))?;
+            } else {
+                eprintln!("Imported {} rows", count);
+            }
+        }
+        Command::Export { table, output } => {
+            let table = fetch_table(client, &table, None, true)?;
+            let mut exported = Vec::new();
+            if json {
+                serde_json::to_writer_pretty(&mut exported, &table)?;
+            } else {
+                write_csv(&mut exported, &table)?;
+            }
+            //The decrypted values must not be readable by other users
+            match output {
+                Some(path) => config::write_private(&path, &exported)?,
+                None => io::Write::write_all(&mut io::stdout(), &exported)?,
+            }
+        }
+        Command::Template { file, output } => {
+            let rendered = template::render(client, &fs::read_to_string(file)?)?;
+            match output {
+                Some(path) => config::write_private(&path, rendered.as_bytes())?,
+                None => print!("{}", rendered),
+            }
+        }
+        Command::Run {
+            table,
+            mappings,
+            command,
+        } => {
+            let code = exec::run(client, &table, &mappings, &command)?;
+            process::exit(code);
+        }
+        Command::GitCredential {
+            table,
+            key,
+            username_column,
+            read_only,
+            action,
+        } => {
+            let mapping = git::CredentialMapping {
+                table,
+                key_pattern: key,
+                username_column,
+            };
+            git::credential(client, &mapping, &action, read_only)?;
+        }
+        Command::DockerCredential {
+            table,
+            username_column,
+            secret_column,
+            action,
+        } => {
+            let mapping = docker::CredentialMapping {
+                table,
+                username_column,
+                secret_column,
+            };
+            //Docker shows the output of a failed helper as the error message
+            if let Err(e) = docker::credential(client, &mapping, &action) {
+                println!("{}", e);
+                process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the table with the given name or id
+///
+/// If a query is given, only the rows whose key contains it are fetched. Decrypting is recorded in the audit log of
+/// the vault, so it is only done if the values are needed.
+fn fetch_table(
+    client: &Client,
+    table: &str,
+    query: Option<&str>,
+    decrypt: bool,
+) -> Result<VaultTable, ClientError> {
+    let entry = client.find_table(table)?;
+    client.table(entry.id, query, decrypt)
+}
+
+/// Returns the only row with the given key
+fn find_row<'a>(table: &'a VaultTable, key: &str) -> Result<&'a client::TableRow, ClientError> {
+    match table.rows_with_key(key)[..] {
+        [row] => Ok(row),
+        [] => Err(ClientError::Other(format!(
+            "There is no row with the key {} in {}",
+            key, table.name
+        ))),
+        _ => Err(ClientError::Other(format!(
+            "There are several rows with the key {} in {}",
+            key, table.name
+        ))),
+    }
+}
+
+/// Writes the table in the same format as the export of the website, which can be imported again
+fn write_csv<W: io::Write>(writer: W, table: &VaultTable) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(table.columns.iter().map(|c| &c.ui_name))?;
+    for row in &table.rows {
+        writer.write_record(
+            table
+                .columns
+                .iter()
+                .map(|c| row.cell(&c.column_name).unwrap_or_default()),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> serde_json::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Contains `spv template`, which fills in values of the vault in a file
+//!
+//! The file is a handlebars template, just like the pages of the vault. The `vault` helper is replaced with a decrypted
+//! cell, for example `{{ vault "Customers" "acme" "password" }}`. The column can be left out, it is the password
+//! column by default.
+
+use crate::client::{Client, VaultTable, PASSWORD_COLUMN};
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+};
+use std::{collections, error, sync};
+
+struct VaultHelper<'a> {
+    client: &'a Client,
+    /// The fetched tables, with the rows of one key each
+    rows: sync::Mutex<collections::HashMap<(String, String), VaultTable>>,
+}
+
+impl VaultHelper<'_> {
+    fn value(&self, table: &str, key: &str, column: &str) -> Result<String, Box<dyn error::Error>> {
+        let mut rows = self.rows.lock().map_err(|_| "The cache is poisoned")?;
+        let cache_key = (table.to_string(), key.to_string());
+        if !rows.contains_key(&cache_key) {
+            let fetched = crate::fetch_table(self.client, table, Some(key), true)?;
+            rows.insert(cache_key.clone(), fetched);
+        }
+        let table = &rows[&cache_key];
+        let column = table
+            .column(column)
+            .ok_or_else(|| format!("There is no column {} in {}", column, table.name))?;
+        Ok(crate::find_row(table, key)?
+            .cell(&column.column_name)
+            .unwrap_or_default()
+            .to_string())
+    }
+}
+
+impl HelperDef for VaultHelper<'_> {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let param = |i: usize| h.param(i).and_then(|p| p.value().as_str());
+        let (table, key, column) = match (param(0), param(1), param(2), h.params().len()) {
+            (Some(table), Some(key), None, 2) => (table, key, PASSWORD_COLUMN),
+            (Some(table), Some(key), Some(column), 3) => (table, key, column),
+            _ => {
+                return Err(RenderError::new(
+                    "vault expects a table, a key and optionally a column as strings",
+                ))
+            }
+        };
+        match self.value(table, key, column) {
+            Ok(value) => Ok(out.write(&value)?),
+            Err(e) => Err(RenderError::new(format!(
+                "{} \"{}\" \"{}\" \"{}\": {}",
+                h.name(),
+                table,
+                key,
+                column,
+                e
+            ))),
+        }
+    }
+}
+
+/// Renders the template, every reference has to exist
+pub fn render(client: &Client, template: &str) -> Result<String, Box<dyn error::Error>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+    //The output is a config file, not a web page
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.register_helper(
+        "vault",
+        Box::new(VaultHelper {
+            client,
+            rows: sync::Mutex::new(collections::HashMap::new()),
+        }),
+    );
+    Ok(handlebars.render_template(template, &())?)
+}
@@ -0,0 +1,144 @@
+//! Contains the protection of state-changing form submissions against cross-site request forgery
+//!
+//! Every session gets its own random csrf token, which is rendered into every form as the hidden field `_csrf`.
+//! Routes that change state accept their form data through [`CsrfForm`], which rejects submissions without the
+//! correct token.
+
+use crate::sessions::{AuthMethod, SafeSessionManager, WithCookie};
+use rocket::{data, form, http, request};
+use std::ops;
+
+/// The name of the hidden form field that contains the csrf token
+pub const CSRF_FIELD: &str = "_csrf";
+
+/// A data guard that works like `form::Form<T>`, but additionally verifies the csrf token of the session
+///
+/// Requests without a known session cookie are passed through without verification, because a forged request can
+/// only do harm with the session cookie of the attacked user.
+/// Those requests are then rejected by the authentication of the route.
+pub struct CsrfForm<T>(T);
+
+impl<T> ops::Deref for CsrfForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> ops::DerefMut for CsrfForm<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: form::FromForm<'r>> data::FromData<'r> for CsrfForm<T> {
+    type Error = form::Errors<'r>;
+
+    async fn from_data(
+        request: &'r request::Request<'_>,
+        data: data::Data<'r>,
+    ) -> data::Outcome<'r, Self> {
+        let expected_token = match (
+            request.rocket().state::<SafeSessionManager>(),
+            WithCookie::retrieve_token(request).await.ok(),
+        ) {
+            (Some(manager), Some(token)) => manager
+                .lock()
+                .await
+                .get_session_metadata(&token)
+                .map(|s| s.csrf_token),
+            _ => None,
+        };
+        match form::Form::<CsrfFields<T>>::from_data(request, data).await {
+            data::Outcome::Success(fields) => {
+                let fields = fields.into_inner();
+                match expected_token {
+                    Some(expected) if !matches!(fields.token, Some(t) if tokens_equal(t, &expected)) => {
+                        data::Outcome::Failure((
+                            http::Status::Forbidden,
+                            form::Error::validation("invalid csrf token").into(),
+                        ))
+                    }
+                    _ => data::Outcome::Success(CsrfForm(fields.value)),
+                }
+            }
+            data::Outcome::Failure(failure) => data::Outcome::Failure(failure),
+            data::Outcome::Forward(data) => data::Outcome::Forward(data),
+        }
+    }
+}
+
+/// Compares both tokens in constant time to not leak the expected token through timing differences
+fn tokens_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// The form `T` extended by the csrf token field
+struct CsrfFields<'r, T> {
+    token: Option<&'r str>,
+    value: T,
+}
+
+struct CsrfFieldsContext<'r, T: form::FromForm<'r>> {
+    token: Option<&'r str>,
+    value: T::Context,
+}
+
+#[rocket::async_trait]
+impl<'r, T: form::FromForm<'r>> form::FromForm<'r> for CsrfFields<'r, T> {
+    type Context = CsrfFieldsContext<'r, T>;
+
+    fn init(opts: form::Options) -> Self::Context {
+        CsrfFieldsContext {
+            token: None,
+            value: T::init(opts),
+        }
+    }
+
+    fn push_value(ctxt: &mut Self::Context, field: form::ValueField<'r>) {
+        if field.name == CSRF_FIELD {
+            ctxt.token = Some(field.value);
+        } else {
+            T::push_value(&mut ctxt.value, field);
+        }
+    }
+
+    async fn push_data(ctxt: &mut Self::Context, field: form::DataField<'r, '_>) {
+        T::push_data(&mut ctxt.value, field).await;
+    }
+
+    fn push_error(ctxt: &mut Self::Context, error: form::Error<'r>) {
+        T::push_error(&mut ctxt.value, error);
+    }
+
+    fn finalize(ctxt: Self::Context) -> form::Result<'r, Self> {
+        Ok(CsrfFields {
+            token: ctxt.token,
+            value: T::finalize(ctxt.value)?,
+        })
+    }
+}
+
+/// A form without any fields for state-changing routes that only need the csrf verification
+pub struct EmptyForm;
+
+#[rocket::async_trait]
+impl<'r> form::FromForm<'r> for EmptyForm {
+    type Context = ();
+
+    fn init(_opts: form::Options) -> Self::Context {}
+
+    fn push_value(_ctxt: &mut Self::Context, _field: form::ValueField<'r>) {}
+
+    async fn push_data(_ctxt: &mut Self::Context, _field: form::DataField<'r, '_>) {}
+
+    fn finalize(_ctxt: Self::Context) -> form::Result<'r, Self> {
+        Ok(EmptyForm)
+    }
+}
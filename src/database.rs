@@ -1,5 +1,8 @@
+use crate::audit::{self, AuditAction, AuditRecord};
+use crate::permissions::{self, Permissions, TableAccess};
 use crate::VaultConfig;
 use rocket::fairing;
+use rocket::tokio::sync;
 use sqlx::{mysql, Row};
 use std::collections;
 
@@ -9,7 +12,14 @@ pub use data::*;
 
 const EXTRA_COLUMN_PREFIX: &str = "extra_";
 
-pub struct VaultDb(mysql::MySqlPool);
+/// The database pool, a lock that makes sure that audit log entries are chained one after another and the key of the
+/// chain
+pub struct VaultDb(mysql::MySqlPool, sync::Mutex<()>, Vec<u8>);
+
+/// Chains the audit log with the configured key once, the entries of older versions were chained without a key
+const AUDIT_LOG_KEY_MIGRATION: &str = "audit_log_key";
+/// Creates the default roles on the first launch
+const DEFAULT_ROLES_MIGRATION: &str = "default_roles";
 
 type QueryResult = sqlx::Result<sqlx::mysql::MySqlQueryResult>;
 
@@ -18,7 +28,7 @@ impl VaultDb {
     pub async fn fairing() -> impl fairing::Fairing {
         fairing::AdHoc::try_on_ignite("Database", |rocket| async move {
             if let Some(config) = rocket.state::<VaultConfig>() {
-                match VaultDb::init(&config.db_url).await {
+                match VaultDb::init(&config.db_url, config.audit_log_key.as_bytes()).await {
                     Ok(db) => return Ok(rocket.manage(db)),
                     Err(e) => {
                         rocket::error!(
@@ -33,12 +43,12 @@ impl VaultDb {
         })
     }
 
-    pub async fn init(db_url: &str) -> Result<Self, sqlx::Error> {
+    pub async fn init(db_url: &str, audit_log_key: &[u8]) -> Result<Self, sqlx::Error> {
         let db: Self = log_and_return(
             mysql::MySqlPoolOptions::new()
                 .connect(db_url)
                 .await
-                .map(Self),
+                .map(|pool| Self(pool, sync::Mutex::new(()), audit_log_key.to_vec())),
         )?;
         db.setup().await?;
         Ok(db)
@@ -54,6 +64,105 @@ impl VaultDb {
         self.create_auth_table().await.map(|qr| {
             rocket::debug!("Successfully created auth table: {:?}", qr);
         })?;
+        self.add_column_if_missing("auth", "disabled", "boolean NOT NULL DEFAULT false")
+            .await?;
+        self.add_column_if_missing("auth", "password_reset", "boolean NOT NULL DEFAULT false")
+            .await?;
+        self.add_column_if_missing(
+            "auth",
+            "provider",
+            "varchar(16) NOT NULL DEFAULT 'database'",
+        )
+        .await?;
+        self.create_roles_table().await.map(|qr| {
+            rocket::debug!("Successfully created roles table: {:?}", qr);
+        })?;
+        self.create_role_assignment_table().await.map(|qr| {
+            rocket::debug!("Successfully created role assignment table: {:?}", qr);
+        })?;
+        self.create_table_grant_table().await.map(|qr| {
+            rocket::debug!("Successfully created table grant table: {:?}", qr);
+        })?;
+        self.create_group_table().await.map(|qr| {
+            rocket::debug!("Successfully created group table: {:?}", qr);
+        })?;
+        self.create_group_member_table().await.map(|qr| {
+            rocket::debug!("Successfully created group member table: {:?}", qr);
+        })?;
+        self.create_group_role_table().await.map(|qr| {
+            rocket::debug!("Successfully created group role table: {:?}", qr);
+        })?;
+        self.create_table_group_grant_table().await.map(|qr| {
+            rocket::debug!("Successfully created table group grant table: {:?}", qr);
+        })?;
+        self.add_column_if_missing(
+            "table_index",
+            "restricted",
+            "boolean NOT NULL DEFAULT false",
+        )
+        .await?;
+        self.restrict_granted_tables().await?;
+        self.create_audit_log_table().await.map(|qr| {
+            rocket::debug!("Successfully created audit log table: {:?}", qr);
+        })?;
+        self.create_migration_table().await.map(|qr| {
+            rocket::debug!("Successfully created migration table: {:?}", qr);
+        })?;
+        if !self.is_migration_applied(AUDIT_LOG_KEY_MIGRATION).await? {
+            self.chain_audit_log_entries().await?;
+            self.insert_migration(AUDIT_LOG_KEY_MIGRATION).await?;
+        }
+        self.create_access_token_table().await.map(|qr| {
+            rocket::debug!("Successfully created access token table: {:?}", qr);
+        })?;
+        //Tokens of older versions could always decrypt
+        self.add_column_if_missing("access_token", "decrypt", "boolean NOT NULL DEFAULT true")
+            .await?;
+        self.create_access_token_table_table().await.map(|qr| {
+            rocket::debug!("Successfully created access token table table: {:?}", qr);
+        })?;
+        //Deleted default roles must not be created again on the next launch
+        if !self.is_migration_applied(DEFAULT_ROLES_MIGRATION).await? {
+            for (name, permissions) in permissions::default_roles() {
+                self.insert_role_if_missing(name, permissions).await?;
+            }
+            self.insert_migration(DEFAULT_ROLES_MIGRATION).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds a column to an existing table, used to migrate tables that were created by older versions
+    async fn add_column_if_missing(
+        &self,
+        table_name: &str,
+        column_name: &str,
+        definition: &str,
+    ) -> sqlx::Result<()> {
+        let exists: i64 = log_and_return(
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?",
+            )
+            .bind(table_name)
+            .bind(column_name)
+            .fetch_one(&self.0)
+            .await,
+        )?;
+        if exists == 0 {
+            let qr = log_and_return(
+                sqlx::query(&format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table_name, column_name, definition
+                ))
+                .execute(&self.0)
+                .await,
+            )?;
+            rocket::debug!(
+                "Successfully added column {} to table {}: {:?}",
+                column_name,
+                table_name,
+                qr
+            );
+        }
         Ok(())
     }
 }
@@ -84,6 +193,95 @@ impl VaultDb {
         )
     }
 
+    pub async fn create_roles_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS roles (id int UNSIGNED PRIMARY KEY AUTO_INCREMENT, name varchar(64) NOT NULL UNIQUE, permissions int UNSIGNED NOT NULL, created datetime NOT NULL DEFAULT CURRENT_TIMESTAMP)")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_role_assignment_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS role_assignment (auth_id int UNSIGNED NOT NULL, role_id int UNSIGNED NOT NULL, PRIMARY KEY (auth_id, role_id))")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_table_grant_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS table_grant (table_id int UNSIGNED NOT NULL, auth_id int UNSIGNED NOT NULL, access tinyint UNSIGNED NOT NULL, PRIMARY KEY (table_id, auth_id))")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_group_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS user_group (id int UNSIGNED PRIMARY KEY AUTO_INCREMENT, name varchar(64) NOT NULL UNIQUE, created datetime NOT NULL DEFAULT CURRENT_TIMESTAMP)")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_group_member_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS group_member (group_id int UNSIGNED NOT NULL, auth_id int UNSIGNED NOT NULL, PRIMARY KEY (group_id, auth_id))")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_group_role_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS group_role (group_id int UNSIGNED NOT NULL, role_id int UNSIGNED NOT NULL, PRIMARY KEY (group_id, role_id))")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_table_group_grant_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS table_group_grant (table_id int UNSIGNED NOT NULL, group_id int UNSIGNED NOT NULL, access tinyint UNSIGNED NOT NULL, PRIMARY KEY (table_id, group_id))")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    /// Records the migrations that must only run once, like rewriting data of older versions
+    pub async fn create_migration_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS migration (name varchar(64) PRIMARY KEY, applied datetime NOT NULL DEFAULT CURRENT_TIMESTAMP)")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_audit_log_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS audit_log (id int UNSIGNED PRIMARY KEY AUTO_INCREMENT, created datetime NOT NULL DEFAULT CURRENT_TIMESTAMP, auth_id int UNSIGNED NOT NULL, action varchar(64) NOT NULL, table_id int UNSIGNED, row_id int UNSIGNED, column_name varchar(64), details varchar(256), hash char(64) NOT NULL)")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_access_token_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS access_token (id int UNSIGNED PRIMARY KEY AUTO_INCREMENT, auth_id int UNSIGNED NOT NULL, name varchar(64) NOT NULL, token_hash char(64) NOT NULL UNIQUE, permissions int UNSIGNED NOT NULL, all_tables boolean NOT NULL, decrypt boolean NOT NULL, created datetime NOT NULL DEFAULT CURRENT_TIMESTAMP, expires datetime, last_used datetime)")
+                .execute(&self.0)
+                .await
+        )
+    }
+
+    pub async fn create_access_token_table_table(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query("CREATE TABLE IF NOT EXISTS access_token_table (token_id int UNSIGNED NOT NULL, table_id int UNSIGNED NOT NULL, PRIMARY KEY (token_id, table_id))")
+                .execute(&self.0)
+                .await
+        )
+    }
+
     pub async fn create_vault_table(
         &self,
         ui_name: &str,
@@ -182,6 +380,27 @@ impl VaultDb {
         log_and_return(query.execute(&self.0).await)
     }
 
+    /// Inserts an account whose password is checked by the given external provider
+    ///
+    /// The stored password hash is random, so that the account can never be used with a local password.
+    pub async fn insert_external_account(
+        &self,
+        name: &str,
+        provider: &str,
+        admin: bool,
+    ) -> QueryResult {
+        log_and_return(
+            sqlx::query(
+                "INSERT INTO auth (name, password_hash, admin, provider) VALUES (?, SHA2(UUID(), 256), ?, ?)",
+            )
+            .bind(name)
+            .bind(admin)
+            .bind(provider)
+            .execute(&self.0)
+            .await,
+        )
+    }
+
     pub async fn insert_password(&self, name: &str, password: &str, admin: bool) -> QueryResult {
         log_and_return(
             sqlx::query(
@@ -194,176 +413,1073 @@ impl VaultDb {
             .await,
         )
     }
-}
 
-// Fetch statements
-impl VaultDb {
-    pub async fn fetch_table_index(&self) -> sqlx::Result<Vec<TableIndexEntry>> {
+    pub async fn insert_role(&self, name: &str, permissions: Permissions) -> QueryResult {
         log_and_return(
-            sqlx::query_as::<_, TableIndexEntry>("SELECT * FROM table_index")
-                .fetch_all(&self.0)
+            sqlx::query("INSERT INTO roles (name, permissions) VALUES (?, ?)")
+                .bind(name)
+                .bind(permissions)
+                .execute(&self.0)
                 .await,
         )
     }
 
-    pub async fn fetch_table_index_entry(&self, id: u64) -> sqlx::Result<Option<TableIndexEntry>> {
+    async fn insert_role_if_missing(&self, name: &str, permissions: Permissions) -> QueryResult {
         log_and_return(
-            sqlx::query_as::<_, TableIndexEntry>("SELECT * FROM table_index WHERE id = ?")
-                .bind(id)
-                .fetch_optional(&self.0)
+            sqlx::query("INSERT IGNORE INTO roles (name, permissions) VALUES (?, ?)")
+                .bind(name)
+                .bind(permissions)
+                .execute(&self.0)
                 .await,
         )
     }
 
-    pub async fn fetch_column_index(
-        &self,
-        table_name: &str,
-    ) -> sqlx::Result<Vec<ColumnIndexEntry>> {
+    pub async fn insert_role_assignment(&self, auth_id: u64, role_id: u64) -> QueryResult {
         log_and_return(
-            sqlx::query_as::<_, ColumnIndexEntry>(
-                "SELECT * FROM column_index WHERE table_name = ?",
-            )
-            .bind(table_name)
-            .fetch_all(&self.0)
-            .await,
+            sqlx::query("INSERT IGNORE INTO role_assignment (auth_id, role_id) VALUES (?, ?)")
+                .bind(auth_id)
+                .bind(role_id)
+                .execute(&self.0)
+                .await,
         )
     }
 
-    pub async fn fetch_column_index_by_id(&self, id: u64) -> sqlx::Result<Vec<ColumnIndexEntry>> {
-        self.fetch_column_index(&gen_vault_table_name(id)).await
+    /// Marks a table as restricted, so only accounts with a grant can access it even after all grants are revoked
+    pub async fn restrict_table(&self, table_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("UPDATE table_index SET restricted = true WHERE id = ?")
+                .bind(table_id)
+                .execute(&self.0)
+                .await,
+        )
     }
 
-    pub async fn fetch_table(
-        &self,
-        id: u64,
-        query: &Option<String>,
-    ) -> sqlx::Result<Option<VaultTable>> {
-        if let Some(table_index) = self.fetch_table_index_entry(id).await? {
-            let table_name = gen_vault_table_name(table_index.id);
-            let column_index = self.fetch_column_index(&table_name).await?;
-            let statement = match query {
-                Some(_) => format!(
-                    "SELECT DISTINCT * FROM {} WHERE LOWER(key_) LIKE LOWER(?) ESCAPE '!'",
-                    table_name
-                ),
-                None => format!("SELECT * FROM {}", table_name),
-            };
-            let data = log_and_return(
-                sqlx::query(&statement)
-                    .bind(query.as_ref().map(|s| gen_search_string(s)))
-                    .fetch_all(&self.0)
-                    .await,
-            )?
-            .into_iter()
-            .map(|r| {
-                let id: u64 = r.get("id");
-                let cells = column_index
-                    .iter()
-                    .filter_map(|c| {
-                        Some(TableCell {
-                            data: r.try_get(&*c.column_name).ok()?,
-                            encrypted: c.encrypted,
-                        })
-                    })
-                    .collect();
-
-                TableRow { id, cells }
-            })
-            .collect();
-            sqlx::Result::Ok(Some(VaultTable {
-                id,
-                name: table_index.ui_name,
-                columns: column_index,
-                rows: data,
-            }))
-        } else {
-            sqlx::Result::Ok(None)
-        }
+    /// Marks all tables with grants as restricted, used to migrate grants that were created by older versions
+    async fn restrict_granted_tables(&self) -> QueryResult {
+        log_and_return(
+            sqlx::query(
+                "UPDATE table_index SET restricted = true WHERE EXISTS (SELECT 1 FROM table_grant WHERE table_grant.table_id = table_index.id) OR EXISTS (SELECT 1 FROM table_group_grant WHERE table_group_grant.table_id = table_index.id)",
+            )
+            .execute(&self.0)
+            .await,
+        )
     }
 
-    pub async fn fetch_all_password(&self, only_admin: bool) -> sqlx::Result<Vec<Password>> {
-        log_and_return(
-            sqlx::query_as::<_, Password>("SELECT * FROM auth WHERE IF(?, admin = 1, true)")
-                .bind(only_admin)
-                .fetch_all(&self.0)
+    async fn is_migration_applied(&self, name: &str) -> sqlx::Result<bool> {
+        let applied: i64 = log_and_return(
+            sqlx::query_scalar("SELECT COUNT(*) FROM migration WHERE name = ?")
+                .bind(name)
+                .fetch_one(&self.0)
                 .await,
-        )
+        )?;
+        Ok(applied > 0)
     }
 
-    pub async fn fetch_password(&self, password: &str) -> sqlx::Result<Option<Password>> {
+    async fn insert_migration(&self, name: &str) -> QueryResult {
         log_and_return(
-            sqlx::query_as::<_, Password>("SELECT * FROM auth WHERE password_hash = SHA2(?, 256)")
-                .bind(password)
-                .fetch_optional(&self.0)
+            sqlx::query("INSERT INTO migration (name) VALUES (?)")
+                .bind(name)
+                .execute(&self.0)
                 .await,
         )
     }
-}
 
-// Delete Statements
-impl VaultDb {
-    pub async fn delete_vault_row(&self, table_id: u64, row_id: u64) -> QueryResult {
+    /// Recalculates the whole chain of the audit log with the configured key, used to migrate entries of older versions
+    ///
+    /// Only runs once, afterwards every entry without a valid hash is reported by the verification.
+    async fn chain_audit_log_entries(&self) -> sqlx::Result<()> {
+        let _lock = self.1.lock().await;
+        let mut previous_hash = String::new();
+        for entry in self.fetch_full_audit_log().await? {
+            let hash = audit::hash_entry(
+                &self.2,
+                &previous_hash,
+                &entry.created,
+                entry.auth_id,
+                &entry.action,
+                entry.table_id,
+                entry.row_id,
+                entry.column_name.as_deref(),
+                entry.details.as_deref(),
+            );
+            log_and_return(
+                sqlx::query("UPDATE audit_log SET hash = ? WHERE id = ?")
+                    .bind(&hash)
+                    .bind(entry.id)
+                    .execute(&self.0)
+                    .await,
+            )?;
+            previous_hash = hash;
+        }
+        Ok(())
+    }
+
+    /// Grants access on a table to an account, the table is restricted from now on
+    pub async fn insert_table_grant(
+        &self,
+        table_id: u64,
+        auth_id: u64,
+        access: TableAccess,
+    ) -> QueryResult {
+        self.restrict_table(table_id).await?;
         log_and_return(
-            sqlx::query(&format!(
-                "DELETE FROM {} WHERE id = ?",
-                gen_vault_table_name(table_id)
-            ))
-            .bind(row_id)
+            sqlx::query(
+                "INSERT INTO table_grant (table_id, auth_id, access) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE access = VALUES(access)",
+            )
+            .bind(table_id)
+            .bind(auth_id)
+            .bind(access)
             .execute(&self.0)
             .await,
         )
     }
 
-    pub async fn delete_password(&self, id: u64) -> QueryResult {
+    pub async fn insert_group(&self, name: &str) -> QueryResult {
         log_and_return(
-            sqlx::query("DELETE FROM auth WHERE id = ?")
-                .bind(id)
+            sqlx::query("INSERT INTO user_group (name) VALUES (?)")
+                .bind(name)
                 .execute(&self.0)
                 .await,
         )
     }
 
-    pub async fn delete_column_index(&self, table_id: u64) -> QueryResult {
+    pub async fn insert_group_member(&self, group_id: u64, auth_id: u64) -> QueryResult {
         log_and_return(
-            sqlx::query("DELETE FROM column_index WHERE table_name = ?")
-                .bind(gen_vault_table_name(table_id))
+            sqlx::query("INSERT IGNORE INTO group_member (group_id, auth_id) VALUES (?, ?)")
+                .bind(group_id)
+                .bind(auth_id)
                 .execute(&self.0)
                 .await,
         )
     }
 
-    pub async fn delete_table_index_entry(&self, table_id: u64) -> QueryResult {
+    pub async fn insert_group_role(&self, group_id: u64, role_id: u64) -> QueryResult {
         log_and_return(
-            sqlx::query("DELETE FROM table_index WHERE id = ?")
-                .bind(table_id)
+            sqlx::query("INSERT IGNORE INTO group_role (group_id, role_id) VALUES (?, ?)")
+                .bind(group_id)
+                .bind(role_id)
                 .execute(&self.0)
                 .await,
         )
     }
 
-    pub async fn delete_vault_table(&self, id: u64) -> QueryResult {
-        self.delete_column_index(id).await?;
-        self.delete_table_index_entry(id).await?;
+    /// Grants access on a table to a group, the table is restricted from now on
+    pub async fn insert_table_group_grant(
+        &self,
+        table_id: u64,
+        group_id: u64,
+        access: TableAccess,
+    ) -> QueryResult {
+        self.restrict_table(table_id).await?;
         log_and_return(
-            sqlx::query(&format!("DROP TABLE {}", gen_vault_table_name(id)))
+            sqlx::query(
+                "INSERT INTO table_group_grant (table_id, group_id, access) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE access = VALUES(access)",
+            )
+            .bind(table_id)
+            .bind(group_id)
+            .bind(access)
+            .execute(&self.0)
+            .await,
+        )
+    }
+
+    /// Stores a new personal access token, only its hash is known to the vault
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_access_token(
+        &self,
+        auth_id: u64,
+        name: &str,
+        token_hash: &str,
+        permissions: Permissions,
+        all_tables: bool,
+        decrypt: bool,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> QueryResult {
+        log_and_return(
+            sqlx::query(
+                "INSERT INTO access_token (auth_id, name, token_hash, permissions, all_tables, decrypt, expires) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(auth_id)
+            .bind(name)
+            .bind(token_hash)
+            .bind(permissions)
+            .bind(all_tables)
+            .bind(decrypt)
+            .bind(expires)
+            .execute(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn insert_access_token_table(&self, token_id: u64, table_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("INSERT INTO access_token_table (token_id, table_id) VALUES (?, ?)")
+                .bind(token_id)
+                .bind(table_id)
                 .execute(&self.0)
                 .await,
         )
     }
-}
 
-// From: https://stackoverflow.com/a/8248052/10772729
-fn gen_search_string(query: &str) -> String {
-    format!(
-        "%{}%",
-        query
-            .replace("!", "!!")
-            .replace("%", "%%")
-            .replace("_", "!_")
+    /// Appends a new entry to the audit log, chained to the last entry
+    pub async fn insert_audit_log_entry(&self, auth_id: u64, record: &AuditRecord) -> QueryResult {
+        let _lock = self.1.lock().await;
+        let previous_hash: String = log_and_return(
+            sqlx::query_scalar("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1")
+                .fetch_optional(&self.0)
+                .await,
+        )?
+        .unwrap_or_default();
+        //The database only stores whole seconds
+        let created = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0);
+        let hash = audit::hash_entry(
+            &self.2,
+            &previous_hash,
+            &created,
+            auth_id,
+            record.action.name(),
+            record.table_id,
+            record.row_id,
+            record.column_name.as_deref(),
+            record.details.as_deref(),
+        );
+        log_and_return(
+            sqlx::query(
+                "INSERT INTO audit_log (created, auth_id, action, table_id, row_id, column_name, details, hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(created)
+            .bind(auth_id)
+            .bind(record.action.name())
+            .bind(record.table_id)
+            .bind(record.row_id)
+            .bind(&record.column_name)
+            .bind(&record.details)
+            .bind(hash)
+            .execute(&self.0)
+            .await,
+        )
+    }
+}
+
+// Update statements
+impl VaultDb {
+    pub async fn update_role_permissions(&self, id: u64, permissions: Permissions) -> QueryResult {
+        log_and_return(
+            sqlx::query("UPDATE roles SET permissions = ? WHERE id = ?")
+                .bind(permissions)
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn update_password(&self, id: u64, password: &str) -> QueryResult {
+        log_and_return(
+            sqlx::query(
+                "UPDATE auth SET password_hash = SHA2(?, 256), password_reset = false WHERE id = ?",
+            )
+            .bind(password)
+            .bind(id)
+            .execute(&self.0)
+            .await,
+        )
+    }
+
+    /// Updates an account and, if given, its password in one transaction
+    ///
+    /// Returns false without changing anything if the password can't be used, because it is already taken.
+    pub async fn update_account(
+        &self,
+        id: u64,
+        name: &str,
+        admin: bool,
+        disabled: bool,
+        password_reset: bool,
+        password: Option<&str>,
+    ) -> sqlx::Result<bool> {
+        let mut transaction = log_and_return(self.0.begin().await)?;
+        log_and_return(
+            sqlx::query(
+                "UPDATE auth SET name = ?, admin = ?, disabled = ?, password_reset = ? WHERE id = ?",
+            )
+            .bind(name)
+            .bind(admin)
+            .bind(disabled)
+            .bind(password_reset)
+            .bind(id)
+            .execute(&mut transaction)
+            .await,
+        )?;
+        if let Some(password) = password {
+            match sqlx::query("UPDATE auth SET password_hash = SHA2(?, 256) WHERE id = ?")
+                .bind(password)
+                .bind(id)
+                .execute(&mut transaction)
+                .await
+            {
+                Ok(_) => {}
+                //Dropping the transaction rolls back the account update
+                Err(sqlx::Error::Database(_)) => return Ok(false),
+                Err(e) => return log_and_return(Err(e)),
+            }
+        }
+        log_and_return(transaction.commit().await)?;
+        Ok(true)
+    }
+
+    /// Updates the given columns of a row, the column names have to be taken from the column index
+    pub async fn update_vault_row(
+        &self,
+        table_id: u64,
+        row_id: u64,
+        data: collections::HashMap<&str, &str>,
+    ) -> QueryResult {
+        let assignments = data
+            .keys()
+            .map(|c| format!("{} = ?", c))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let statement = format!(
+            "UPDATE {} SET {} WHERE id = ?",
+            gen_vault_table_name(table_id),
+            assignments
+        );
+        let mut query = sqlx::query(&statement);
+        for datum in data {
+            query = query.bind(datum.1);
+        }
+        log_and_return(query.bind(row_id).execute(&self.0).await)
+    }
+
+    pub async fn update_access_token_last_used(&self, id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("UPDATE access_token SET last_used = ? WHERE id = ?")
+                .bind(chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0))
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn update_admin(&self, id: u64, admin: bool) -> QueryResult {
+        log_and_return(
+            sqlx::query("UPDATE auth SET admin = ? WHERE id = ?")
+                .bind(admin)
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn update_group_name(&self, id: u64, name: &str) -> QueryResult {
+        log_and_return(
+            sqlx::query("UPDATE user_group SET name = ? WHERE id = ?")
+                .bind(name)
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+}
+
+// Fetch statements
+impl VaultDb {
+    pub async fn fetch_table_index(&self) -> sqlx::Result<Vec<TableIndexEntry>> {
+        log_and_return(
+            sqlx::query_as::<_, TableIndexEntry>("SELECT * FROM table_index")
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    /// Fetches all tables that the given account can access
+    ///
+    /// Tables that were never restricted are accessible by every account.
+    pub async fn fetch_accessible_table_index(
+        &self,
+        auth_id: u64,
+    ) -> sqlx::Result<Vec<TableIndexEntry>> {
+        log_and_return(
+            sqlx::query_as::<_, TableIndexEntry>(
+                "SELECT * FROM table_index WHERE restricted = false OR EXISTS (SELECT 1 FROM table_grant WHERE table_grant.table_id = table_index.id AND table_grant.auth_id = ?) OR EXISTS (SELECT 1 FROM table_group_grant INNER JOIN group_member ON group_member.group_id = table_group_grant.group_id WHERE table_group_grant.table_id = table_index.id AND group_member.auth_id = ?)",
+            )
+            .bind(auth_id)
+            .bind(auth_id)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_table_index_entry(&self, id: u64) -> sqlx::Result<Option<TableIndexEntry>> {
+        log_and_return(
+            sqlx::query_as::<_, TableIndexEntry>("SELECT * FROM table_index WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_column_index(
+        &self,
+        table_name: &str,
+    ) -> sqlx::Result<Vec<ColumnIndexEntry>> {
+        log_and_return(
+            sqlx::query_as::<_, ColumnIndexEntry>(
+                "SELECT * FROM column_index WHERE table_name = ?",
+            )
+            .bind(table_name)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_column_index_by_id(&self, id: u64) -> sqlx::Result<Vec<ColumnIndexEntry>> {
+        self.fetch_column_index(&gen_vault_table_name(id)).await
+    }
+
+    pub async fn fetch_table(
+        &self,
+        id: u64,
+        query: &Option<String>,
+    ) -> sqlx::Result<Option<VaultTable>> {
+        if let Some(table_index) = self.fetch_table_index_entry(id).await? {
+            let table_name = gen_vault_table_name(table_index.id);
+            let column_index = self.fetch_column_index(&table_name).await?;
+            let statement = match query {
+                Some(_) => format!(
+                    "SELECT DISTINCT * FROM {} WHERE LOWER(key_) LIKE LOWER(?) ESCAPE '!'",
+                    table_name
+                ),
+                None => format!("SELECT * FROM {}", table_name),
+            };
+            let data = log_and_return(
+                sqlx::query(&statement)
+                    .bind(query.as_ref().map(|s| gen_search_string(s)))
+                    .fetch_all(&self.0)
+                    .await,
+            )?
+            .iter()
+            .map(|r| to_table_row(r, &column_index))
+            .collect();
+            sqlx::Result::Ok(Some(VaultTable {
+                id,
+                name: table_index.ui_name,
+                columns: column_index,
+                rows: data,
+            }))
+        } else {
+            sqlx::Result::Ok(None)
+        }
+    }
+
+    /// Fetches a single row of a vault table
+    pub async fn fetch_row(&self, table_id: u64, row_id: u64) -> sqlx::Result<Option<TableRow>> {
+        let table_name = gen_vault_table_name(table_id);
+        let column_index = self.fetch_column_index(&table_name).await?;
+        let row = log_and_return(
+            sqlx::query(&format!("SELECT * FROM {} WHERE id = ?", table_name))
+                .bind(row_id)
+                .fetch_optional(&self.0)
+                .await,
+        )?;
+        Ok(row.map(|r| to_table_row(&r, &column_index)))
+    }
+
+    /// Fetches a single cell of a vault table
+    pub async fn fetch_cell(
+        &self,
+        table_id: u64,
+        row_id: u64,
+        column_name: &str,
+    ) -> sqlx::Result<Option<TableCell>> {
+        let table_name = gen_vault_table_name(table_id);
+        let column = match self
+            .fetch_column_index(&table_name)
+            .await?
+            .into_iter()
+            .find(|c| c.column_name == column_name)
+        {
+            Some(column) => column,
+            None => return Ok(None),
+        };
+        //The column name comes from the column index and can be safely used in the statement
+        let statement = format!(
+            "SELECT {} FROM {} WHERE id = ?",
+            column.column_name, table_name
+        );
+        let data: Option<Option<String>> = log_and_return(
+            sqlx::query_scalar(&statement)
+                .bind(row_id)
+                .fetch_optional(&self.0)
+                .await,
+        )?;
+        Ok(data.flatten().map(|data| TableCell {
+            column: column.column_name,
+            data,
+            encrypted: column.encrypted,
+        }))
+    }
+
+    /// Fetches the newest audit log entries that match all given filters
+    pub async fn fetch_audit_log(
+        &self,
+        action: Option<AuditAction>,
+        auth_id: Option<u64>,
+        table_id: Option<u64>,
+        limit: u32,
+    ) -> sqlx::Result<Vec<AuditLogEntry>> {
+        log_and_return(
+            sqlx::query_as::<_, AuditLogEntry>(
+                "SELECT audit_log.id, audit_log.created, audit_log.auth_id, auth.name, audit_log.action, audit_log.table_id, audit_log.row_id, audit_log.column_name, audit_log.details, audit_log.hash FROM audit_log LEFT JOIN auth ON auth.id = audit_log.auth_id \
+                WHERE (? IS NULL OR audit_log.action = ?) AND (? IS NULL OR audit_log.auth_id = ?) AND (? IS NULL OR audit_log.table_id = ?) ORDER BY audit_log.id DESC LIMIT ?",
+            )
+            .bind(action.map(|a| a.name()))
+            .bind(action.map(|a| a.name()))
+            .bind(auth_id)
+            .bind(auth_id)
+            .bind(table_id)
+            .bind(table_id)
+            .bind(limit)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    /// Fetches the whole audit log ordered from the oldest to the newest entry
+    pub async fn fetch_full_audit_log(&self) -> sqlx::Result<Vec<AuditLogEntry>> {
+        log_and_return(
+            sqlx::query_as::<_, AuditLogEntry>(
+                "SELECT audit_log.id, audit_log.created, audit_log.auth_id, auth.name, audit_log.action, audit_log.table_id, audit_log.row_id, audit_log.column_name, audit_log.details, audit_log.hash FROM audit_log LEFT JOIN auth ON auth.id = audit_log.auth_id ORDER BY audit_log.id",
+            )
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_table_grants(&self, table_id: u64) -> sqlx::Result<Vec<TableGrant>> {
+        log_and_return(
+            sqlx::query_as::<_, TableGrant>(
+                "SELECT table_grant.table_id, table_grant.auth_id, auth.name, table_grant.access FROM table_grant INNER JOIN auth ON auth.id = table_grant.auth_id WHERE table_grant.table_id = ?",
+            )
+            .bind(table_id)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_table_group_grants(
+        &self,
+        table_id: u64,
+    ) -> sqlx::Result<Vec<TableGroupGrant>> {
+        log_and_return(
+            sqlx::query_as::<_, TableGroupGrant>(
+                "SELECT table_group_grant.table_id, table_group_grant.group_id, user_group.name, table_group_grant.access FROM table_group_grant INNER JOIN user_group ON user_group.id = table_group_grant.group_id WHERE table_group_grant.table_id = ?",
+            )
+            .bind(table_id)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    /// Returns whether access on the table is restricted to the accounts and groups with a grant
+    pub async fn fetch_table_restricted(&self, table_id: u64) -> sqlx::Result<bool> {
+        Ok(log_and_return(
+            sqlx::query_scalar::<_, bool>("SELECT restricted FROM table_index WHERE id = ?")
+                .bind(table_id)
+                .fetch_optional(&self.0)
+                .await,
+        )?
+        .unwrap_or(false))
+    }
+
+    /// Returns the access the given account has on the given table
+    ///
+    /// Grants to groups apply to all of their members.
+    /// Tables that were never restricted can be fully accessed by every account. A restricted table stays restricted
+    /// when its last grant is revoked, so only admins can access it then.
+    pub async fn fetch_table_access(
+        &self,
+        table_id: u64,
+        auth_id: u64,
+    ) -> sqlx::Result<Option<TableAccess>> {
+        if !self.fetch_table_restricted(table_id).await? {
+            return Ok(Some(TableAccess::Manage));
+        }
+        let grants = log_and_return(
+            sqlx::query_as::<_, (Option<u64>, TableAccess)>(
+                "SELECT auth_id, access FROM table_grant WHERE table_id = ? UNION ALL SELECT group_member.auth_id, table_group_grant.access FROM table_group_grant LEFT JOIN group_member ON group_member.group_id = table_group_grant.group_id AND group_member.auth_id = ? WHERE table_group_grant.table_id = ?",
+            )
+            .bind(table_id)
+            .bind(auth_id)
+            .bind(table_id)
+            .fetch_all(&self.0)
+            .await,
+        )?;
+        Ok(grants
+            .into_iter()
+            .filter(|g| g.0 == Some(auth_id))
+            .map(|g| g.1)
+            .max())
+    }
+
+    pub async fn fetch_all_password(&self, only_admin: bool) -> sqlx::Result<Vec<Password>> {
+        log_and_return(
+            sqlx::query_as::<_, Password>("SELECT * FROM auth WHERE IF(?, admin = 1, true)")
+                .bind(only_admin)
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_password_by_id(&self, id: u64) -> sqlx::Result<Option<Password>> {
+        log_and_return(
+            sqlx::query_as::<_, Password>("SELECT * FROM auth WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.0)
+                .await,
+        )
+    }
+
+    /// Returns whether any enabled admin account other than the given one exists
+    pub async fn exists_other_admin(&self, id: u64) -> sqlx::Result<bool> {
+        let count: i64 = log_and_return(
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM auth WHERE admin = 1 AND disabled = 0 AND id != ?",
+            )
+            .bind(id)
+            .fetch_one(&self.0)
+            .await,
+        )?;
+        Ok(count > 0)
+    }
+
+    pub async fn fetch_password(&self, password: &str) -> sqlx::Result<Option<Password>> {
+        log_and_return(
+            sqlx::query_as::<_, Password>(
+                "SELECT * FROM auth WHERE password_hash = SHA2(?, 256) AND provider = 'database'",
+            )
+            .bind(password)
+            .fetch_optional(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_external_account(
+        &self,
+        name: &str,
+        provider: &str,
+    ) -> sqlx::Result<Option<Password>> {
+        log_and_return(
+            sqlx::query_as::<_, Password>("SELECT * FROM auth WHERE name = ? AND provider = ?")
+                .bind(name)
+                .bind(provider)
+                .fetch_optional(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_access_tokens(&self, auth_id: u64) -> sqlx::Result<Vec<AccessToken>> {
+        log_and_return(
+            sqlx::query_as::<_, AccessToken>(
+                "SELECT * FROM access_token WHERE auth_id = ? ORDER BY created DESC",
+            )
+            .bind(auth_id)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_access_token_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> sqlx::Result<Option<AccessToken>> {
+        log_and_return(
+            sqlx::query_as::<_, AccessToken>("SELECT * FROM access_token WHERE token_hash = ?")
+                .bind(token_hash)
+                .fetch_optional(&self.0)
+                .await,
+        )
+    }
+
+    /// Fetches the tables of all access tokens of the given account
+    pub async fn fetch_access_token_tables(
+        &self,
+        auth_id: u64,
+    ) -> sqlx::Result<Vec<AccessTokenTable>> {
+        log_and_return(
+            sqlx::query_as::<_, AccessTokenTable>(
+                "SELECT access_token_table.token_id, access_token_table.table_id, table_index.ui_name FROM access_token_table INNER JOIN access_token ON access_token.id = access_token_table.token_id INNER JOIN table_index ON table_index.id = access_token_table.table_id WHERE access_token.auth_id = ?",
+            )
+            .bind(auth_id)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_access_token_table_ids(&self, token_id: u64) -> sqlx::Result<Vec<u64>> {
+        log_and_return(
+            sqlx::query_scalar("SELECT table_id FROM access_token_table WHERE token_id = ?")
+                .bind(token_id)
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_all_roles(&self) -> sqlx::Result<Vec<Role>> {
+        log_and_return(
+            sqlx::query_as::<_, Role>("SELECT * FROM roles ORDER BY id")
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_role_by_name(&self, name: &str) -> sqlx::Result<Option<Role>> {
+        log_and_return(
+            sqlx::query_as::<_, Role>("SELECT * FROM roles WHERE name = ?")
+                .bind(name)
+                .fetch_optional(&self.0)
+                .await,
+        )
+    }
+
+    /// Fetches all roles of the given account, including the roles of its groups
+    pub async fn fetch_roles_of_password(&self, auth_id: u64) -> sqlx::Result<Vec<Role>> {
+        log_and_return(
+            sqlx::query_as::<_, Role>(
+                "SELECT * FROM roles WHERE id IN (SELECT role_id FROM role_assignment WHERE auth_id = ?) OR id IN (SELECT group_role.role_id FROM group_role INNER JOIN group_member ON group_member.group_id = group_role.group_id WHERE group_member.auth_id = ?)",
+            )
+            .bind(auth_id)
+            .bind(auth_id)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    /// Returns the ids of all accounts that get the permissions of the given role
+    ///
+    /// These are the accounts the role is assigned to, directly or through a group, and all accounts without any role if
+    /// it is the default role.
+    pub async fn fetch_role_account_ids(
+        &self,
+        role_id: u64,
+        default_role: &str,
+    ) -> sqlx::Result<Vec<u64>> {
+        log_and_return(
+            sqlx::query_scalar(
+                "SELECT id FROM auth WHERE id IN (SELECT auth_id FROM role_assignment WHERE role_id = ?) OR id IN (SELECT group_member.auth_id FROM group_member INNER JOIN group_role ON group_role.group_id = group_member.group_id WHERE group_role.role_id = ?) OR (EXISTS (SELECT 1 FROM roles WHERE id = ? AND name = ?) AND id NOT IN (SELECT auth_id FROM role_assignment) AND id NOT IN (SELECT group_member.auth_id FROM group_member INNER JOIN group_role ON group_role.group_id = group_member.group_id))",
+            )
+            .bind(role_id)
+            .bind(role_id)
+            .bind(role_id)
+            .bind(default_role)
+            .fetch_all(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn fetch_all_groups(&self) -> sqlx::Result<Vec<Group>> {
+        log_and_return(
+            sqlx::query_as::<_, Group>("SELECT * FROM user_group ORDER BY name")
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_group_member_ids(&self, group_id: u64) -> sqlx::Result<Vec<u64>> {
+        log_and_return(
+            sqlx::query_scalar("SELECT auth_id FROM group_member WHERE group_id = ?")
+                .bind(group_id)
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_all_group_members(&self) -> sqlx::Result<Vec<GroupMember>> {
+        log_and_return(
+            sqlx::query_as::<_, GroupMember>("SELECT * FROM group_member")
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_all_group_roles(&self) -> sqlx::Result<Vec<GroupRole>> {
+        log_and_return(
+            sqlx::query_as::<_, GroupRole>("SELECT * FROM group_role")
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn fetch_all_role_assignments(&self) -> sqlx::Result<Vec<RoleAssignment>> {
+        log_and_return(
+            sqlx::query_as::<_, RoleAssignment>("SELECT * FROM role_assignment")
+                .fetch_all(&self.0)
+                .await,
+        )
+    }
+
+    /// Returns the combined permissions of all roles assigned to the given account
+    ///
+    /// Admin accounts always get all permissions.
+    /// Accounts without any assigned role get the permissions of the role called `default_role`.
+    pub async fn fetch_permissions(
+        &self,
+        password: &Password,
+        default_role: &str,
+    ) -> sqlx::Result<Permissions> {
+        if password.admin {
+            return Ok(Permissions::all());
+        }
+        let mut roles = self.fetch_roles_of_password(password.id).await?;
+        if roles.is_empty() {
+            roles.extend(self.fetch_role_by_name(default_role).await?);
+        }
+        Ok(roles
+            .into_iter()
+            .fold(Permissions::none(), |p, r| p.union(r.permissions)))
+    }
+}
+
+// Delete Statements
+impl VaultDb {
+    pub async fn delete_vault_row(&self, table_id: u64, row_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE id = ?",
+                gen_vault_table_name(table_id)
+            ))
+            .bind(row_id)
+            .execute(&self.0)
+            .await,
+        )
+    }
+
+    pub async fn delete_password(&self, id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM role_assignment WHERE auth_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM table_grant WHERE auth_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM group_member WHERE auth_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE access_token_table FROM access_token_table INNER JOIN access_token ON access_token.id = access_token_table.token_id WHERE access_token.auth_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM access_token WHERE auth_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM auth WHERE id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    /// Deletes an access token of the given account
+    pub async fn delete_access_token(&self, id: u64, auth_id: u64) -> QueryResult {
+        let result = log_and_return(
+            sqlx::query("DELETE FROM access_token WHERE id = ? AND auth_id = ?")
+                .bind(id)
+                .bind(auth_id)
+                .execute(&self.0)
+                .await,
+        )?;
+        if result.rows_affected() > 0 {
+            log_and_return(
+                sqlx::query("DELETE FROM access_token_table WHERE token_id = ?")
+                    .bind(id)
+                    .execute(&self.0)
+                    .await,
+            )?;
+        }
+        Ok(result)
+    }
+
+    pub async fn delete_role(&self, id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM role_assignment WHERE role_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM group_role WHERE role_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM roles WHERE id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_role_assignment(&self, auth_id: u64, role_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM role_assignment WHERE auth_id = ? AND role_id = ?")
+                .bind(auth_id)
+                .bind(role_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_table_grant(&self, table_id: u64, auth_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM table_grant WHERE table_id = ? AND auth_id = ?")
+                .bind(table_id)
+                .bind(auth_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_table_group_grant(&self, table_id: u64, group_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM table_group_grant WHERE table_id = ? AND group_id = ?")
+                .bind(table_id)
+                .bind(group_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_table_grants(&self, table_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM table_group_grant WHERE table_id = ?")
+                .bind(table_id)
+                .execute(&self.0)
+                .await,
+        )?;
+        log_and_return(
+            sqlx::query("DELETE FROM table_grant WHERE table_id = ?")
+                .bind(table_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_group(&self, id: u64) -> QueryResult {
+        for statement in [
+            "DELETE FROM group_member WHERE group_id = ?",
+            "DELETE FROM group_role WHERE group_id = ?",
+            "DELETE FROM table_group_grant WHERE group_id = ?",
+        ] {
+            log_and_return(sqlx::query(statement).bind(id).execute(&self.0).await)?;
+        }
+        log_and_return(
+            sqlx::query("DELETE FROM user_group WHERE id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_group_member(&self, group_id: u64, auth_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM group_member WHERE group_id = ? AND auth_id = ?")
+                .bind(group_id)
+                .bind(auth_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_group_role(&self, group_id: u64, role_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM group_role WHERE group_id = ? AND role_id = ?")
+                .bind(group_id)
+                .bind(role_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_column_index(&self, table_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM column_index WHERE table_name = ?")
+                .bind(gen_vault_table_name(table_id))
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_table_index_entry(&self, table_id: u64) -> QueryResult {
+        log_and_return(
+            sqlx::query("DELETE FROM table_index WHERE id = ?")
+                .bind(table_id)
+                .execute(&self.0)
+                .await,
+        )
+    }
+
+    pub async fn delete_vault_table(&self, id: u64) -> QueryResult {
+        self.delete_column_index(id).await?;
+        self.delete_table_grants(id).await?;
+        log_and_return(
+            sqlx::query("DELETE FROM access_token_table WHERE table_id = ?")
+                .bind(id)
+                .execute(&self.0)
+                .await,
+        )?;
+        self.delete_table_index_entry(id).await?;
+        log_and_return(
+            sqlx::query(&format!("DROP TABLE {}", gen_vault_table_name(id)))
+                .execute(&self.0)
+                .await,
+        )
+    }
+}
+
+// From: https://stackoverflow.com/a/8248052/10772729
+fn gen_search_string(query: &str) -> String {
+    format!(
+        "%{}%",
+        query
+            .replace("!", "!!")
+            .replace("%", "%%")
+            .replace("_", "!_")
             .replace("[", "![")
     )
 }
 
+fn to_table_row(row: &mysql::MySqlRow, column_index: &[ColumnIndexEntry]) -> TableRow {
+    let cells = column_index
+        .iter()
+        .filter_map(|c| {
+            Some(TableCell {
+                column: c.column_name.clone(),
+                data: row.try_get(&*c.column_name).ok()?,
+                encrypted: c.encrypted,
+            })
+        })
+        .collect();
+    TableRow {
+        id: row.get("id"),
+        cells,
+    }
+}
+
 fn gen_vault_table_name(id: u64) -> String {
     format!("vault_{}", id)
 }
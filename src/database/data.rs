@@ -1,4 +1,5 @@
 use crate::crypt;
+use crate::permissions::{Permissions, TableAccess};
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize)]
 pub struct Password {
@@ -6,16 +7,93 @@ pub struct Password {
     pub name: String,
     pub password_hash: String,
     pub admin: bool,
+    pub disabled: bool,
+    pub password_reset: bool,
+    /// The authentication provider that checks the password of this account
+    pub provider: String,
     pub created: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Role {
+    pub id: u64,
+    pub name: String,
+    pub permissions: Permissions,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct RoleAssignment {
+    pub auth_id: u64,
+    pub role_id: u64,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Group {
+    pub id: u64,
+    pub name: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct GroupMember {
+    pub group_id: u64,
+    pub auth_id: u64,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct GroupRole {
+    pub group_id: u64,
+    pub role_id: u64,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, schemars::JsonSchema)]
 pub struct TableIndexEntry {
     pub id: u64,
     pub ui_name: String,
 }
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct TableGrant {
+    pub table_id: u64,
+    pub auth_id: u64,
+    pub name: String,
+    pub access: TableAccess,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct TableGroupGrant {
+    pub table_id: u64,
+    pub group_id: u64,
+    pub name: String,
+    pub access: TableAccess,
+}
+
+/// A personal access token, only the hash of the token itself is stored
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct AccessToken {
+    pub id: u64,
+    pub auth_id: u64,
+    pub name: String,
+    /// The scopes of the token, the permissions of the account are restricted to them
+    pub permissions: Permissions,
+    /// Whether the token can access all tables, otherwise only the tables in `access_token_table`
+    pub all_tables: bool,
+    /// Whether the token can decrypt values, it can't confirm a password instead
+    pub decrypt: bool,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub expires: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_used: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct AccessTokenTable {
+    pub token_id: u64,
+    pub table_id: u64,
+    pub ui_name: String,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, schemars::JsonSchema)]
 pub struct ColumnIndexEntry {
     pub id: u64,
     pub table_name: String,
@@ -25,7 +103,7 @@ pub struct ColumnIndexEntry {
     pub encrypted: bool,
 }
 
-#[derive(Default, Debug, serde::Serialize)]
+#[derive(Default, Debug, serde::Serialize, schemars::JsonSchema)]
 pub struct VaultTable {
     pub id: u64,
     pub name: String,
@@ -33,14 +111,15 @@ pub struct VaultTable {
     pub rows: Vec<TableRow>,
 }
 
-#[derive(Default, Debug, serde::Serialize)]
+#[derive(Default, Debug, serde::Serialize, schemars::JsonSchema)]
 pub struct TableRow {
     pub id: u64,
     pub cells: Vec<TableCell>,
 }
 
-#[derive(Default, Debug, serde::Serialize)]
+#[derive(Default, Debug, serde::Serialize, schemars::JsonSchema)]
 pub struct TableCell {
+    pub column: String,
     pub data: String,
     pub encrypted: bool,
 }
@@ -57,6 +136,17 @@ impl VaultTable {
         Ok(String::from_utf8(wtr.into_inner()?)?)
     }
 
+    /// Removes the data of all encrypted cells, so that it can't be revealed
+    pub fn hide_encrypted(&mut self) {
+        for row in &mut self.rows {
+            for cell in &mut row.cells {
+                if cell.encrypted {
+                    cell.data.clear();
+                }
+            }
+        }
+    }
+
     pub fn decrypt(&mut self, keypair: &crypt::KeyPair) {
         for row in &mut self.rows {
             for mut cell in &mut row.cells {
@@ -69,3 +159,18 @@ impl VaultTable {
         }
     }
 }
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct AuditLogEntry {
+    pub id: u64,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub auth_id: u64,
+    /// The name of the account, `None` if the account was deleted
+    pub name: Option<String>,
+    pub action: String,
+    pub table_id: Option<u64>,
+    pub row_id: Option<u64>,
+    pub column_name: Option<String>,
+    pub details: Option<String>,
+    pub hash: String,
+}
@@ -0,0 +1,508 @@
+//! Contains the structured security events and the sinks they are written to
+//!
+//! Route handlers emit events through the managed [`EventSender`].
+//! A background task writes them to all configured sinks, so that a slow or unreachable sink never delays a request.
+
+use crate::audit::AuditRecord;
+use crate::VaultConfig;
+use rocket::fairing;
+use rocket::serde::json::serde_json;
+use rocket::tokio::io::AsyncWriteExt;
+use rocket::tokio::{fs, io, net, sync::mpsc};
+use std::path;
+
+/// The number of events that can be queued before new events are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The structured data id of the syslog messages, `32473` is the private enterprise number reserved for documentation
+const SYSLOG_SD_ID: &str = "vault@32473";
+
+#[derive(Debug, Copy, Clone, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SecurityEvent {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The name of the event, the same as the action name in the audit log
+    pub event: String,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl SecurityEvent {
+    pub fn new(event: &str, outcome: Outcome) -> Self {
+        Self {
+            timestamp: chrono::Utc::now(),
+            event: event.to_string(),
+            outcome,
+            user_id: None,
+            client_ip: None,
+            table_id: None,
+            row_id: None,
+            column_name: None,
+            details: None,
+        }
+    }
+
+    /// Creates the event of a successful action that is recorded in the audit log
+    pub fn from_record(user_id: u64, record: &AuditRecord) -> Self {
+        Self {
+            user_id: Some(user_id),
+            table_id: record.table_id,
+            row_id: record.row_id,
+            column_name: record.column_name.clone(),
+            details: record.details.clone(),
+            ..Self::new(record.action.name(), Outcome::Success)
+        }
+    }
+
+    pub fn with_user(mut self, user_id: u64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn with_client_ip(mut self, client_ip: Option<std::net::IpAddr>) -> Self {
+        self.client_ip = client_ip.map(|ip| ip.to_string());
+        self
+    }
+
+    pub fn with_details<S: Into<String>>(mut self, details: S) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventSinkConfig {
+    /// RFC 5424 syslog messages
+    Syslog {
+        transport: SyslogTransport,
+        /// `host:port` for `udp` and `tcp`, the socket path for `unix`
+        address: String,
+        #[serde(default = "default_facility")]
+        facility: u8,
+        #[serde(default = "default_app_name")]
+        app_name: String,
+        /// The hostname that is sent in every message, the nil value `-` is sent if it is not set
+        hostname: Option<String>,
+    },
+    /// One JSON object per line, the file is rotated once it reaches `max_size_bytes`
+    File {
+        path: path::PathBuf,
+        #[serde(default = "default_max_size")]
+        max_size_bytes: u64,
+        /// The number of rotated files that are kept
+        #[serde(default = "default_max_files")]
+        max_files: u32,
+    },
+    /// One JSON object per line on stdout
+    Stdout,
+}
+
+#[derive(Debug, Copy, Clone, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTransport {
+    Udp,
+    Tcp,
+    Unix,
+}
+
+fn default_facility() -> u8 {
+    10 //authpriv
+}
+fn default_app_name() -> String {
+    "simple-password-vault".to_string()
+}
+fn default_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+fn default_max_files() -> u32 {
+    5
+}
+
+/// Queues events for the background task, events are dropped if no sink is configured
+#[derive(Clone)]
+pub struct EventSender(Option<mpsc::Sender<SecurityEvent>>);
+
+impl EventSender {
+    pub fn fairing() -> impl fairing::Fairing {
+        fairing::AdHoc::on_ignite("Security Events", |rocket| async {
+            let sinks = rocket
+                .state::<VaultConfig>()
+                .map(|c| c.event_sinks.clone())
+                .unwrap_or_default();
+            if sinks.is_empty() {
+                return rocket.manage(EventSender(None));
+            }
+            let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+            rocket::tokio::spawn(run(receiver, sinks.into_iter().map(Sink::from).collect()));
+            rocket.manage(EventSender(Some(sender)))
+        })
+    }
+
+    /// Queues the given event without waiting for the sinks
+    pub fn emit(&self, event: SecurityEvent) {
+        if let Some(sender) = &self.0 {
+            if let Err(mpsc::error::TrySendError::Full(event)) = sender.try_send(event) {
+                rocket::warn!("Dropped security event, the queue is full: {:?}", event);
+            }
+        }
+    }
+}
+
+async fn run(mut receiver: mpsc::Receiver<SecurityEvent>, mut sinks: Vec<Sink>) {
+    while let Some(event) = receiver.recv().await {
+        for sink in &mut sinks {
+            if let Err(e) = sink.write(&event).await {
+                rocket::warn!("Failed to write security event: {}", e);
+            }
+        }
+    }
+}
+
+enum Sink {
+    Syslog(SyslogSink),
+    File(FileSink),
+    Stdout,
+}
+
+impl From<EventSinkConfig> for Sink {
+    fn from(config: EventSinkConfig) -> Self {
+        match config {
+            EventSinkConfig::Syslog {
+                transport,
+                address,
+                facility,
+                app_name,
+                hostname,
+            } => Sink::Syslog(SyslogSink {
+                transport,
+                address,
+                facility,
+                app_name,
+                hostname,
+                connection: None,
+            }),
+            EventSinkConfig::File {
+                path,
+                max_size_bytes,
+                max_files,
+            } => Sink::File(FileSink {
+                path,
+                max_size_bytes,
+                max_files,
+                file: None,
+                size: 0,
+            }),
+            EventSinkConfig::Stdout => Sink::Stdout,
+        }
+    }
+}
+
+impl Sink {
+    async fn write(&mut self, event: &SecurityEvent) -> io::Result<()> {
+        match self {
+            Sink::Syslog(sink) => sink.write(event).await,
+            Sink::File(sink) => sink.write(event).await,
+            Sink::Stdout => {
+                let mut line = serde_json::to_vec(event)?;
+                line.push(b'\n');
+                io::stdout().write_all(&line).await
+            }
+        }
+    }
+}
+
+struct SyslogSink {
+    transport: SyslogTransport,
+    address: String,
+    facility: u8,
+    app_name: String,
+    hostname: Option<String>,
+    connection: Option<SyslogConnection>,
+}
+
+impl SyslogSink {
+    async fn write(&mut self, event: &SecurityEvent) -> io::Result<()> {
+        let message = format_syslog(
+            event,
+            self.facility,
+            &self.app_name,
+            self.hostname.as_deref(),
+        );
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => self
+                .connection
+                .insert(SyslogConnection::connect(self.transport, &self.address).await?),
+        };
+        let result = connection.send(&message).await;
+        //Reconnect with the next event
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+}
+
+/// Formats the given event as RFC 5424 syslog message
+fn format_syslog(
+    event: &SecurityEvent,
+    facility: u8,
+    app_name: &str,
+    hostname: Option<&str>,
+) -> String {
+    let severity = match event.outcome {
+        Outcome::Success => 5, //notice
+        Outcome::Failure => 4, //warning
+    };
+    let outcome = match event.outcome {
+        Outcome::Success => "success",
+        Outcome::Failure => "failure",
+    };
+    let mut data = format!("[{} outcome=\"{}\"", SYSLOG_SD_ID, outcome);
+    let params = [
+        ("user_id", event.user_id.map(|i| i.to_string())),
+        ("client_ip", event.client_ip.clone()),
+        ("table_id", event.table_id.map(|i| i.to_string())),
+        ("row_id", event.row_id.map(|i| i.to_string())),
+        ("column_name", event.column_name.clone()),
+    ];
+    for (name, value) in params {
+        if let Some(value) = value {
+            data.push_str(&format!(" {}=\"{}\"", name, escape_param_value(&value)));
+        }
+    }
+    data.push(']');
+    let mut message = format!(
+        "<{}>1 {} {} {} {} {} {}",
+        facility as u16 * 8 + severity,
+        event
+            .timestamp
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        hostname.unwrap_or("-"),
+        app_name,
+        std::process::id(),
+        event.event,
+        data
+    );
+    if let Some(details) = &event.details {
+        message.push(' ');
+        message.push_str(details);
+    }
+    message
+}
+
+/// Escapes the characters that are not allowed in structured data parameter values
+fn escape_param_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+enum SyslogConnection {
+    Udp(net::UdpSocket),
+    Tcp(net::TcpStream),
+    #[cfg(unix)]
+    Unix(net::UnixDatagram),
+}
+
+impl SyslogConnection {
+    async fn connect(transport: SyslogTransport, address: &str) -> io::Result<Self> {
+        match transport {
+            SyslogTransport::Udp => {
+                let remote = net::lookup_host(address).await?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "syslog address not found")
+                })?;
+                let local = if remote.is_ipv6() {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = net::UdpSocket::bind(local).await?;
+                socket.connect(remote).await?;
+                Ok(SyslogConnection::Udp(socket))
+            }
+            SyslogTransport::Tcp => Ok(SyslogConnection::Tcp(
+                net::TcpStream::connect(address).await?,
+            )),
+            #[cfg(unix)]
+            SyslogTransport::Unix => {
+                let socket = net::UnixDatagram::unbound()?;
+                socket.connect(address)?;
+                Ok(SyslogConnection::Unix(socket))
+            }
+            #[cfg(not(unix))]
+            SyslogTransport::Unix => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    async fn send(&mut self, message: &str) -> io::Result<()> {
+        match self {
+            SyslogConnection::Udp(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+            //Octet counting framing (RFC 6587)
+            SyslogConnection::Tcp(stream) => {
+                stream
+                    .write_all(format!("{} {}", message.len(), message).as_bytes())
+                    .await
+            }
+            #[cfg(unix)]
+            SyslogConnection::Unix(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+        }
+    }
+}
+
+struct FileSink {
+    path: path::PathBuf,
+    max_size_bytes: u64,
+    max_files: u32,
+    file: Option<fs::File>,
+    size: u64,
+}
+
+impl FileSink {
+    async fn write(&mut self, event: &SecurityEvent) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        if self.file.is_none() {
+            self.open().await?;
+        }
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size_bytes {
+            self.rotate().await?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(&line).await?;
+            file.flush().await?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    async fn open(&mut self) -> io::Result<()> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        self.size = file.metadata().await?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    /// Renames `<path>` to `<path>.1`, `<path>.1` to `<path>.2` and so on and removes the oldest file
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path).await?;
+        } else {
+            for i in (1..self.max_files).rev() {
+                //The older files don't have to exist yet
+                let _ = fs::rename(self.rotated_path(i), self.rotated_path(i + 1)).await;
+            }
+            fs::rename(&self.path, self.rotated_path(1)).await?;
+        }
+        self.open().await
+    }
+
+    fn rotated_path(&self, i: u32) -> path::PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", i));
+        name.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> SecurityEvent {
+        SecurityEvent {
+            timestamp: chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000, 0).unwrap(),
+            user_id: Some(3),
+            client_ip: Some("192.0.2.1".to_string()),
+            ..SecurityEvent::new("login", Outcome::Failure)
+        }
+    }
+
+    #[test]
+    fn syslog_layout() {
+        assert_eq!(
+            format_syslog(&event(), 10, "vault", Some("host")),
+            format!(
+                "<84>1 2020-09-13T12:26:40.000Z host vault {} login \
+                 [vault@32473 outcome=\"failure\" user_id=\"3\" client_ip=\"192.0.2.1\"]",
+                std::process::id()
+            )
+        );
+    }
+
+    #[test]
+    fn syslog_nil_hostname_and_details() {
+        let event = SecurityEvent {
+            outcome: Outcome::Success,
+            details: Some("wrong password".to_string()),
+            ..event()
+        };
+        let message = format_syslog(&event, 4, "vault", None);
+        assert!(message.starts_with("<37>1 2020-09-13T12:26:40.000Z - vault "));
+        assert!(message.ends_with(
+            "outcome=\"success\" user_id=\"3\" client_ip=\"192.0.2.1\"] wrong password"
+        ));
+    }
+
+    #[test]
+    fn escaped_param_values() {
+        assert_eq!(escape_param_value("a\"b\\c]d"), "a\\\"b\\\\c\\]d");
+        let event = SecurityEvent {
+            column_name: Some("[x]".to_string()),
+            ..event()
+        };
+        assert!(format_syslog(&event, 10, "vault", None).ends_with(" column_name=\"[x\\]\"]"));
+    }
+
+    #[rocket::async_test]
+    async fn rotated_file_names() {
+        let dir = std::env::temp_dir().join(format!("vault-events-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let mut sink = FileSink {
+            path: dir.join("events.log"),
+            max_size_bytes: 1,
+            max_files: 2,
+            file: None,
+            size: 0,
+        };
+        assert_eq!(sink.rotated_path(2), dir.join("events.log.2"));
+        //Every event exceeds the size, so each one rotates the previous file
+        for _ in 0..4 {
+            sink.write(&event()).await.unwrap();
+        }
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name().into_string().unwrap());
+        }
+        names.sort();
+        fs::remove_dir_all(&dir).await.unwrap();
+        assert_eq!(names, ["events.log", "events.log.1", "events.log.2"]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Contains the fairing that adds security related headers to every response
+
+use crate::VaultConfig;
+use rocket::{fairing, request, response};
+
+/// Path prefixes of pages that contain decrypted secrets, which must never be stored by the browser or any proxy
+///
+/// The secrets engine is added with its configured mount.
+const NO_STORE_PATHS: &[&str] = &["/vault", "/reveal", "/download", "/account/tokens", "/api"];
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SecurityHeadersConfig {
+    #[serde(default = "default_content_security_policy")]
+    pub content_security_policy: String,
+    /// The `max-age` of the `Strict-Transport-Security` header, `0` disables the header
+    #[serde(default = "default_hsts_max_age")]
+    pub hsts_max_age_secs: u64,
+}
+
+fn default_content_security_policy() -> String {
+    "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self'; \
+     object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'"
+        .to_string()
+}
+fn default_hsts_max_age() -> u64 {
+    31536000
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            content_security_policy: default_content_security_policy(),
+            hsts_max_age_secs: default_hsts_max_age(),
+        }
+    }
+}
+
+pub struct SecurityHeaders;
+
+#[rocket::async_trait]
+impl fairing::Fairing for SecurityHeaders {
+    fn info(&self) -> fairing::Info {
+        fairing::Info {
+            name: "Security Headers",
+            kind: fairing::Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(
+        &self,
+        request: &'r request::Request<'_>,
+        response: &mut response::Response<'r>,
+    ) {
+        let path = request.uri().path().as_str();
+        let mut no_store = NO_STORE_PATHS.iter().any(|p| path.starts_with(p));
+        if let Some(config) = request.rocket().state::<VaultConfig>() {
+            if let Some(kv) = &config.kv {
+                no_store |= path.starts_with(&format!("/v1/{}", kv.mount));
+            }
+            let config = &config.security_headers;
+            response.set_raw_header(
+                "Content-Security-Policy",
+                config.content_security_policy.clone(),
+            );
+            if config.hsts_max_age_secs > 0 {
+                response.set_raw_header(
+                    "Strict-Transport-Security",
+                    format!("max-age={}", config.hsts_max_age_secs),
+                );
+            }
+        }
+        response.set_raw_header("X-Frame-Options", "DENY");
+        response.set_raw_header("X-Content-Type-Options", "nosniff");
+        response.set_raw_header("Referrer-Policy", "no-referrer");
+        if no_store {
+            response.set_raw_header("Cache-Control", "no-store");
+        }
+    }
+}
@@ -8,8 +8,15 @@ use crate::database::VaultDb;
 use rocket::{fairing, fs, serde};
 use rocket_dyn_templates as templates;
 
+mod audit;
+mod auth;
 mod crypt;
+mod csrf;
 mod database;
+mod events;
+mod headers;
+mod permissions;
+mod policy;
 mod routes;
 mod sessions;
 
@@ -24,10 +31,27 @@ pub struct VaultConfig {
     token_length: u32,
     #[serde(default = "default_token_validity")]
     token_validity_duration_secs: u64,
+    #[serde(default = "default_sudo_duration")]
+    sudo_duration_secs: u64,
     #[serde(default = "default_public_key")]
     public_key_path: String,
     #[serde(default = "default_private_key")]
     private_key_path: String,
+    #[serde(default = "default_role")]
+    default_role: String,
+    /// The secret key of the audit log's hash chain
+    audit_log_key: String,
+    #[serde(default)]
+    password_policy: policy::PasswordPolicy,
+    #[serde(default)]
+    session_cookie: sessions::SessionCookieConfig,
+    #[serde(default)]
+    security_headers: headers::SecurityHeadersConfig,
+    #[serde(default)]
+    event_sinks: Vec<events::EventSinkConfig>,
+    ldap: Option<auth::ldap::LdapConfig>,
+    oidc: Option<auth::oidc::OidcConfig>,
+    kv: Option<routes::kv::KvConfig>,
 }
 
 fn default_name() -> String {
@@ -42,12 +66,18 @@ fn default_token_length() -> u32 {
 fn default_token_validity() -> u64 {
     86400
 }
+fn default_sudo_duration() -> u64 {
+    900
+}
 fn default_public_key() -> String {
     "keys/rsapubkey.pem".to_string()
 }
 fn default_private_key() -> String {
     "keys/rsakey.pem".to_string()
 }
+fn default_role() -> String {
+    "table-manager".to_string()
+}
 
 #[rocket::main]
 async fn main() {
@@ -56,15 +86,35 @@ async fn main() {
         .attach(VaultDb::fairing().await)
         .attach(crypt::KeyPair::fairing().await)
         .attach(sessions::SessionManager::fairing())
+        .attach(events::EventSender::fairing())
+        .attach(auth::Authenticator::fairing())
+        .attach(auth::oidc::OidcClient::fairing())
         .attach(templates::Template::fairing())
+        .attach(headers::SecurityHeaders)
+        .attach(routes::api::openapi::fairing())
+        .mount("/", routes::account::get_routes())
         .mount("/", routes::admin::get_routes())
+        .mount("/", routes::audit::get_routes())
+        .mount("/", routes::roles::get_routes())
+        .mount("/", routes::groups::get_routes())
         .mount("/", routes::authentication::get_routes())
+        .mount("/", routes::oidc::get_routes())
         .mount("/", routes::xport::get_routes())
         .mount("/", routes::table_cud::get_routes())
-        .mount("/", routes::vault::get_routes());
+        .mount("/", routes::vault::get_routes())
+        .mount("/api", routes::api::openapi::get_routes())
+        .mount("/api/v1", routes::api::get_routes())
+        .register("/api/v1", routes::api::get_catchers());
 
     match rocket.figment().extract::<VaultConfig>() {
         Ok(config) => {
+            let rocket = match &config.kv {
+                Some(kv) => rocket
+                    .mount(format!("/v1/{}", kv.mount), routes::kv::get_routes())
+                    .mount("/v1/sys", routes::kv::get_sys_routes())
+                    .register("/v1", routes::kv::get_catchers()),
+                None => rocket,
+            };
             rocket
                 .mount("/", fs::FileServer::from(&config.static_dir))
                 .launch()
@@ -0,0 +1,166 @@
+//! Contains the permissions that can be granted to accounts through roles
+
+use serde::ser::SerializeMap;
+
+/// A single action that an account can be allowed to perform
+#[derive(Copy, Clone, Debug, PartialEq, Eq, rocket::FromFormField)]
+pub enum Permission {
+    /// View tables and their data
+    #[field(value = "read")]
+    Read,
+    /// Add and remove table rows
+    #[field(value = "write")]
+    Write,
+    /// Create and delete tables
+    #[field(value = "manage_tables")]
+    ManageTables,
+    /// Import table data from csv files
+    #[field(value = "import")]
+    Import,
+    /// Export (download) table data as csv files
+    #[field(value = "export")]
+    Export,
+    /// Manage accounts and roles
+    #[field(value = "administrate")]
+    Administrate,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 6] = [
+        Permission::Read,
+        Permission::Write,
+        Permission::ManageTables,
+        Permission::Import,
+        Permission::Export,
+        Permission::Administrate,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::ManageTables => "manage_tables",
+            Permission::Import => "import",
+            Permission::Export => "export",
+            Permission::Administrate => "administrate",
+        }
+    }
+
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// A set of permissions, stored as a bit mask in the database
+///
+/// Serializes to a map of all permission names to a boolean, so templates can access e.g. `permissions.write`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    pub fn none() -> Self {
+        Self(0)
+    }
+
+    pub fn all() -> Self {
+        Self::from_iter(Permission::ALL)
+    }
+
+    pub fn contains(&self, permission: Permission) -> bool {
+        self.0 & permission.bit() != 0
+    }
+
+    pub fn insert(&mut self, permission: Permission) {
+        self.0 |= permission.bit();
+    }
+
+    pub fn remove(&mut self, permission: Permission) {
+        self.0 &= !permission.bit();
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl FromIterator<Permission> for Permissions {
+    fn from_iter<I: IntoIterator<Item = Permission>>(iter: I) -> Self {
+        let mut permissions = Self::none();
+        for p in iter {
+            permissions.insert(p);
+        }
+        permissions
+    }
+}
+
+impl serde::Serialize for Permissions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(Permission::ALL.len()))?;
+        for p in Permission::ALL {
+            map.serialize_entry(p.name(), &self.contains(p))?;
+        }
+        map.end()
+    }
+}
+
+/// The roles that are created on first launch
+pub fn default_roles() -> Vec<(&'static str, Permissions)> {
+    use Permission::*;
+    vec![
+        ("viewer", Permissions::from_iter([Read])),
+        ("editor", Permissions::from_iter([Read, Write])),
+        (
+            "table-manager",
+            Permissions::from_iter([Read, Write, ManageTables, Import, Export]),
+        ),
+        ("admin", Permissions::all()),
+    ]
+}
+
+/// The access an account has been granted on a single table
+///
+/// Every level includes the levels below it.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    rocket::FromFormField,
+    serde::Serialize,
+    sqlx::Type,
+)]
+#[repr(u8)]
+#[serde(rename_all = "lowercase")]
+pub enum TableAccess {
+    /// View the table and export its data
+    #[field(value = "read")]
+    Read = 1,
+    /// Add, remove and import table rows
+    #[field(value = "write")]
+    Write = 2,
+    /// Delete the table and manage its grants
+    #[field(value = "manage")]
+    Manage = 3,
+}
+
+impl TableAccess {
+    /// Removes all table related permissions that are not covered by this access level
+    pub fn restrict(&self, mut permissions: Permissions) -> Permissions {
+        if *self < TableAccess::Manage {
+            permissions.remove(Permission::ManageTables);
+        }
+        if *self < TableAccess::Write {
+            permissions.remove(Permission::Write);
+            permissions.remove(Permission::Import);
+        }
+        permissions
+    }
+}
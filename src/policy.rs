@@ -0,0 +1,191 @@
+//! Contains the password policy that new account passwords have to satisfy
+
+use std::fmt;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PasswordPolicy {
+    #[serde(default = "default_min_length")]
+    pub min_length: usize,
+    #[serde(default)]
+    pub require_lowercase: bool,
+    #[serde(default)]
+    pub require_uppercase: bool,
+    #[serde(default)]
+    pub require_digit: bool,
+    #[serde(default)]
+    pub require_symbol: bool,
+    /// The minimum zxcvbn strength score from 0 (weakest) to 4 (strongest)
+    #[serde(default)]
+    pub min_strength: u8,
+    /// Passwords that are never allowed, compared case-insensitively
+    #[serde(default)]
+    pub banned_passwords: Vec<String>,
+}
+
+fn default_min_length() -> usize {
+    8
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: default_min_length(),
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            min_strength: 0,
+            banned_passwords: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PolicyViolation {
+    TooShort(usize),
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    TooWeak,
+    Banned,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::TooShort(min) => {
+                write!(f, "The password must be at least {} characters long", min)
+            }
+            PolicyViolation::MissingLowercase => {
+                write!(f, "The password must contain a lowercase letter")
+            }
+            PolicyViolation::MissingUppercase => {
+                write!(f, "The password must contain an uppercase letter")
+            }
+            PolicyViolation::MissingDigit => write!(f, "The password must contain a digit"),
+            PolicyViolation::MissingSymbol => {
+                write!(f, "The password must contain a special character")
+            }
+            PolicyViolation::TooWeak => write!(
+                f,
+                "The password is too easy to guess, please choose a stronger one"
+            ),
+            PolicyViolation::Banned => write!(f, "The password is not allowed"),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Checks the given password against this policy
+    ///
+    /// `user_inputs` should contain account specific words (like the account name) that make the password weaker.
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), PolicyViolation> {
+        if password.chars().count() < self.min_length {
+            return Err(PolicyViolation::TooShort(self.min_length));
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            return Err(PolicyViolation::MissingLowercase);
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            return Err(PolicyViolation::MissingUppercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            return Err(PolicyViolation::MissingDigit);
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            return Err(PolicyViolation::MissingSymbol);
+        }
+        if self
+            .banned_passwords
+            .iter()
+            .any(|b| b.to_lowercase() == password.to_lowercase())
+        {
+            return Err(PolicyViolation::Banned);
+        }
+        if self.min_strength > 0 {
+            //An error is only returned for empty passwords
+            let score = zxcvbn::zxcvbn(password, user_inputs).map_or(0, |e| e.score());
+            if score < self.min_strength {
+                return Err(PolicyViolation::TooWeak);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PasswordPolicy, PolicyViolation};
+
+    #[test]
+    fn counts_characters_instead_of_bytes() {
+        let policy = PasswordPolicy::default();
+        assert!(matches!(
+            policy.check("äöüäöüä", &[]),
+            Err(PolicyViolation::TooShort(8))
+        ));
+        assert!(policy.check("äöüäöüäö", &[]).is_ok());
+    }
+
+    #[test]
+    fn requires_the_configured_characters() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..PasswordPolicy::default()
+        };
+        assert!(matches!(
+            policy.check("ABCDEFG1!", &[]),
+            Err(PolicyViolation::MissingLowercase)
+        ));
+        assert!(matches!(
+            policy.check("abcdefg1!", &[]),
+            Err(PolicyViolation::MissingUppercase)
+        ));
+        assert!(matches!(
+            policy.check("abcdefgH!", &[]),
+            Err(PolicyViolation::MissingDigit)
+        ));
+        assert!(matches!(
+            policy.check("abcdefgH1", &[]),
+            Err(PolicyViolation::MissingSymbol)
+        ));
+        assert!(policy.check("abcdefH1!", &[]).is_ok());
+        assert!(policy.check("abcdefH1 ", &[]).is_ok());
+    }
+
+    #[test]
+    fn bans_passwords_case_insensitively() {
+        let policy = PasswordPolicy {
+            banned_passwords: vec!["Winter2021".to_string()],
+            ..PasswordPolicy::default()
+        };
+        assert!(matches!(
+            policy.check("wINTER2021", &[]),
+            Err(PolicyViolation::Banned)
+        ));
+        assert!(policy.check("Winter2022", &[]).is_ok());
+    }
+
+    #[test]
+    fn rejects_guessable_passwords() {
+        let policy = PasswordPolicy {
+            min_strength: 3,
+            ..PasswordPolicy::default()
+        };
+        assert!(matches!(
+            policy.check("password123", &[]),
+            Err(PolicyViolation::TooWeak)
+        ));
+        assert!(policy.check("plume-orbit-cactus-27", &[]).is_ok());
+        //The account name makes a password that contains it easy to guess
+        assert!(policy.check("qzrvtmxlop7", &[]).is_ok());
+        assert!(matches!(
+            policy.check("qzrvtmxlop7", &["qzrvtmxlop"]),
+            Err(PolicyViolation::TooWeak)
+        ));
+    }
+}
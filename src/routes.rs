@@ -1,8 +1,20 @@
-use crate::VaultConfig;
+use crate::audit::AuditRecord;
+use crate::database::TableIndexEntry;
+use crate::events::{EventSender, SecurityEvent};
+use crate::permissions::{Permission, Permissions};
+use crate::sessions::{SafeSessionManager, SessionMetadata};
+use crate::{VaultConfig, VaultDb};
 use rocket::{http, request, response};
 
+pub mod account;
 pub mod admin;
+pub mod api;
+pub mod audit;
 pub mod authentication;
+pub mod groups;
+pub mod kv;
+pub mod oidc;
+pub mod roles;
 pub mod table_cud;
 pub mod vault;
 pub mod xport;
@@ -11,6 +23,8 @@ pub mod xport;
 struct GeneralContext {
     name: String,
     admin: bool,
+    permissions: Permissions,
+    csrf_token: String,
 }
 
 impl From<&VaultConfig> for GeneralContext {
@@ -23,8 +37,14 @@ impl From<&VaultConfig> for GeneralContext {
 }
 
 impl GeneralContext {
-    fn with_admin(mut self, admin: bool) -> Self {
-        self.admin = admin;
+    fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.admin = permissions.contains(Permission::Administrate);
+        self.permissions = permissions;
+        self
+    }
+
+    fn with_csrf_token(mut self, csrf_token: &str) -> Self {
+        self.csrf_token = csrf_token.to_string();
         self
     }
 }
@@ -46,6 +66,11 @@ impl FlashContext {
         self.with_general_context(GeneralContext::from(config))
     }
 
+    fn with_csrf_token(mut self, csrf_token: &str) -> Self {
+        self.general = self.general.with_csrf_token(csrf_token);
+        self
+    }
+
     fn with_optional_flash(mut self, flash: Option<request::FlashMessage>) -> Self {
         self.kind = flash.as_ref().map(|f| f.kind().to_string());
         self.message = flash.as_ref().map(|f| f.message().to_string());
@@ -83,3 +108,153 @@ impl<T> VaultResponse<T> {
         Self::Redirect(response::Redirect::to(uri))
     }
 }
+
+/// Returns the permissions the given session has on the given table
+///
+/// The session's permissions are restricted by the access that was granted on the table.
+/// If the session has no access at all, no permissions are returned.
+/// Sessions with the `administrate` permission can always fully access all tables.
+/// Sessions of personal access tokens are additionally restricted to the tables of the token.
+async fn fetch_table_permissions(
+    database: &VaultDb,
+    session: &SessionMetadata,
+    table_id: u64,
+) -> sqlx::Result<Permissions> {
+    if matches!(&session.tables, Some(tables) if !tables.contains(&table_id)) {
+        Ok(Permissions::none())
+    } else if session.permissions.contains(Permission::Administrate) {
+        Ok(session.permissions)
+    } else {
+        Ok(database
+            .fetch_table_access(table_id, session.user_id)
+            .await?
+            .map_or(Permissions::none(), |a| a.restrict(session.permissions)))
+    }
+}
+
+/// Fetches all tables the given session can access
+async fn fetch_accessible_table_index(
+    database: &VaultDb,
+    session: &SessionMetadata,
+) -> sqlx::Result<Vec<TableIndexEntry>> {
+    let mut index = if session.permissions.contains(Permission::Administrate) {
+        database.fetch_table_index().await?
+    } else {
+        database
+            .fetch_accessible_table_index(session.user_id)
+            .await?
+    };
+    if let Some(tables) = &session.tables {
+        index.retain(|e| tables.contains(&e.id));
+    }
+    Ok(index)
+}
+
+/// Redirects to the given path, or to the vault if it doesn't lead to a page of the vault itself
+fn redirect_to_local<T>(next: &str) -> VaultResponse<T> {
+    //Only local paths are allowed to not redirect to other sites
+    match http::uri::Origin::parse(next) {
+        Ok(next) if !next.path().starts_with("//") => VaultResponse::redirect_to(next.to_string()),
+        _ => VaultResponse::redirect_to(rocket::uri!(vault::vault)),
+    }
+}
+
+/// Makes sure that the given session recently confirmed its password
+///
+/// Returns a redirect to the password confirmation otherwise, which leads back to `next` afterwards.
+fn check_confirmed<T>(
+    config: &VaultConfig,
+    session: &SessionMetadata,
+    next: http::uri::Origin,
+) -> Option<VaultResponse<T>> {
+    if session.is_confirmed_within(std::time::Duration::from_secs(config.sudo_duration_secs)) {
+        None
+    } else {
+        Some(VaultResponse::flash_error_redirect_to(
+            rocket::uri!(account::confirm(next.to_string())),
+            "Please confirm your password to continue",
+        ))
+    }
+}
+
+/// Makes sure that the session with the given token has the given permission on the given table
+///
+/// Returns the session's metadata on success and an appropriate error response otherwise.
+async fn check_table_permission<T>(
+    database: &VaultDb,
+    session_manager: &SafeSessionManager,
+    token: &str,
+    table_id: u64,
+    permission: Permission,
+) -> Result<SessionMetadata, VaultResponse<T>> {
+    let session = session_manager
+        .lock()
+        .await
+        .get_session_metadata(token)
+        .ok_or(VaultResponse::Err(http::Status::Unauthorized))?;
+    match fetch_table_permissions(database, &session, table_id).await {
+        Ok(p) if p.contains(permission) => Ok(session),
+        Ok(_) => Err(VaultResponse::Err(http::Status::Unauthorized)),
+        Err(_) => Err(VaultResponse::Err(http::Status::InternalServerError)),
+    }
+}
+
+/// Records an action of the given account in the audit log and emits it as security event
+///
+/// A failed write is only logged, because the action itself already happened.
+async fn audit_user(database: &VaultDb, events: &EventSender, user_id: u64, record: AuditRecord) {
+    events.emit(SecurityEvent::from_record(user_id, &record));
+    if database
+        .insert_audit_log_entry(user_id, &record)
+        .await
+        .is_err()
+    {
+        rocket::warn!("Failed to write audit log entry: {:?}", record);
+    }
+}
+
+/// Records an action of the given account in the audit log before the action is performed
+///
+/// Unlike [`audit_user`], a failed write is returned, so that actions which must not happen unrecorded can be refused.
+async fn audit_before(
+    database: &VaultDb,
+    events: &EventSender,
+    user_id: u64,
+    record: AuditRecord,
+) -> sqlx::Result<()> {
+    database.insert_audit_log_entry(user_id, &record).await?;
+    events.emit(SecurityEvent::from_record(user_id, &record));
+    Ok(())
+}
+
+/// Logs out the given accounts after their permissions changed, except the session with the given token
+///
+/// Sessions keep the permissions of their login, so they would otherwise keep permissions that were taken away.
+async fn revoke_sessions_of(session_manager: &SafeSessionManager, user_ids: &[u64], except: &str) {
+    let mut session_manager = session_manager.lock().await;
+    for user_id in user_ids {
+        session_manager.revoke_user_sessions(*user_id, Some(except));
+    }
+}
+
+/// Records an action of the session with the given token in the audit log
+async fn audit(
+    database: &VaultDb,
+    events: &EventSender,
+    session_manager: &SafeSessionManager,
+    token: &str,
+    record: AuditRecord,
+) {
+    let user_id = session_manager
+        .lock()
+        .await
+        .get_session_metadata(token)
+        .map(|s| s.user_id);
+    match user_id {
+        Some(user_id) => audit_user(database, events, user_id, record).await,
+        None => rocket::warn!(
+            "Failed to write audit log entry without session: {:?}",
+            record
+        ),
+    }
+}
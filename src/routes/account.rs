@@ -0,0 +1,550 @@
+//! Contains all routes and types of the account settings of the logged-in user
+
+use crate::audit::{AuditAction, AuditRecord};
+use crate::auth::oidc::OIDC_PROVIDER;
+use crate::auth::{Authenticator, DATABASE_PROVIDER};
+use crate::csrf::CsrfForm;
+use crate::database::{AccessToken, AccessTokenTable, TableIndexEntry};
+use crate::events::{EventSender, Outcome, SecurityEvent};
+use crate::permissions::{Permission, Permissions};
+use crate::routes::{FlashContext, GeneralContext, VaultResponse};
+use crate::sessions::{
+    self, SafeSessionManager, SessionMetadata, TokenAuth, TokenAuthResult, WithCookie,
+};
+use crate::{templates, VaultConfig, VaultDb};
+use rocket::{form, http, request};
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        change_password,
+        change_password_submit,
+        confirm,
+        confirm_submit,
+        access_tokens,
+        create_access_token_submit,
+        revoke_access_token_submit
+    ]
+}
+
+#[rocket::get("/account/password")]
+async fn change_password(
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        let permissions = session_manager
+            .lock()
+            .await
+            .get_permissions(token.token())
+            .unwrap_or_default();
+        let context = FlashContext::default()
+            .with_general_context(
+                GeneralContext::from(config.inner())
+                    .with_permissions(permissions)
+                    .with_csrf_token(token.csrf_token()),
+            )
+            .with_optional_flash(flash);
+        VaultResponse::Ok(templates::Template::render("change-password", context))
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct ChangePasswordData<'a> {
+    current_password: &'a str,
+    password: &'a str,
+    #[field(name = "password_confirm", validate = eq(self.password))]
+    _password_confirm: &'a str,
+}
+
+/// Returns the error message of the first invalid field of the password form
+fn change_password_error(context: &form::Context<'_>) -> String {
+    let fields = [
+        ("current_password", "The current password"),
+        ("password", "The new password"),
+        ("password_confirm", "The confirmation of the new password"),
+    ];
+    for (name, label) in fields {
+        if let Some(error) = context.field_errors(name).next() {
+            return match error.kind {
+                form::error::ErrorKind::Validation(_) if name == "password_confirm" => {
+                    "Both new passwords must match".to_string()
+                }
+                form::error::ErrorKind::Missing => format!("{} is missing", label),
+                _ => format!("{} is invalid: {}", label, error),
+            };
+        }
+    }
+    match context.errors().next() {
+        Some(error) => format!("The form is invalid: {}", error),
+        None => "The form is invalid".to_string(),
+    }
+}
+
+#[rocket::post("/account/password", data = "<form>")]
+async fn change_password_submit(
+    auth: TokenAuth<WithCookie>,
+    form: CsrfForm<form::Contextual<'_, ChangePasswordData<'_>>>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    let data = match form.value {
+        Some(ref data) => data,
+        None => {
+            return VaultResponse::flash_error_redirect_to(
+                rocket::uri!(change_password),
+                change_password_error(&form.context),
+            )
+        }
+    };
+    let user_id = match session_manager
+        .lock()
+        .await
+        .get_session_metadata(auth.token())
+    {
+        Some(session) => session.user_id,
+        None => return VaultResponse::Err(http::Status::Unauthorized),
+    };
+    match database.fetch_password_by_id(user_id).await {
+        Ok(Some(p)) if p.provider != DATABASE_PROVIDER => {
+            return VaultResponse::flash_error_redirect_to(
+                rocket::uri!(change_password),
+                format!(
+                    "The password of this account is managed by {} and can't be changed here",
+                    p.provider
+                ),
+            )
+        }
+        Ok(Some(_)) => {}
+        Ok(None) => return VaultResponse::Err(http::Status::Unauthorized),
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    }
+    let password_reset = match database.fetch_password(data.current_password).await {
+        Ok(Some(p)) if p.id == user_id => {
+            if let Err(violation) = config.password_policy.check(data.password, &[&p.name]) {
+                return VaultResponse::flash_error_redirect_to(
+                    rocket::uri!(change_password),
+                    violation.to_string(),
+                );
+            }
+            p.password_reset
+        }
+        Ok(_) => {
+            return VaultResponse::flash_error_redirect_to(
+                rocket::uri!(change_password),
+                "The current password is wrong, please try again",
+            )
+        }
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    let result = database.update_password(user_id, data.password).await;
+    if result.is_ok() {
+        super::audit_user(
+            database,
+            events,
+            user_id,
+            AuditRecord::new(AuditAction::ChangePassword),
+        )
+        .await;
+    }
+    match result {
+        Ok(_) if password_reset => {
+            //The current session was created without any permissions, so a new login is required
+            session_manager
+                .lock()
+                .await
+                .revoke_user_sessions(user_id, None);
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(super::authentication::login),
+                "Changed password, please log in with your new password",
+            )
+        }
+        Ok(_) => {
+            session_manager
+                .lock()
+                .await
+                .revoke_user_sessions(user_id, Some(auth.token()));
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(change_password),
+                "Changed password, all other sessions were logged out",
+            )
+        }
+        Err(sqlx::Error::Database(_)) => VaultResponse::flash_error_redirect_to(
+            rocket::uri!(change_password),
+            "The new password can not be used, please choose a different one",
+        ),
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ConfirmContext {
+    flash: FlashContext,
+    next: String,
+    /// The login at the single sign-on provider, for accounts that don't have a password
+    oidc: Option<String>,
+}
+
+#[rocket::get("/confirm?<next>")]
+async fn confirm(
+    next: String,
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        let session = match session_manager
+            .lock()
+            .await
+            .get_session_metadata(token.token())
+        {
+            Some(session) => session,
+            None => return VaultResponse::Err(http::Status::Unauthorized),
+        };
+        let provider = match database.fetch_password_by_id(session.user_id).await {
+            Ok(Some(account)) => account.provider,
+            Ok(None) => return VaultResponse::Err(http::Status::Unauthorized),
+            Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+        };
+        let oidc = (provider == OIDC_PROVIDER)
+            .then(|| rocket::uri!(super::oidc::oidc_login(Some(&next))).to_string());
+        let context = ConfirmContext {
+            flash: FlashContext::default()
+                .with_general_context(
+                    GeneralContext::from(config.inner())
+                        .with_permissions(session.permissions)
+                        .with_csrf_token(token.csrf_token()),
+                )
+                .with_optional_flash(flash),
+            next,
+            oidc,
+        };
+        VaultResponse::Ok(templates::Template::render("confirm", context))
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct ConfirmData<'a> {
+    password: &'a str,
+    next: &'a str,
+}
+
+#[rocket::post("/confirm", data = "<form>")]
+async fn confirm_submit(
+    auth: TokenAuth<WithCookie>,
+    form: CsrfForm<ConfirmData<'_>>,
+    authenticator: &rocket::State<Authenticator>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    let user_id = match session_manager
+        .lock()
+        .await
+        .get_session_metadata(auth.token())
+    {
+        Some(session) => session.user_id,
+        None => return VaultResponse::Err(http::Status::Unauthorized),
+    };
+    let account = match database.fetch_password_by_id(user_id).await {
+        Ok(Some(account)) => account,
+        Ok(None) => return VaultResponse::Err(http::Status::Unauthorized),
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    match authenticator
+        .verify(database, &account, form.password)
+        .await
+    {
+        Ok(true) => {
+            session_manager.lock().await.confirm_session(auth.token());
+            super::audit_user(
+                database,
+                events,
+                user_id,
+                AuditRecord::new(AuditAction::ConfirmPassword),
+            )
+            .await;
+            super::redirect_to_local(form.next)
+        }
+        Ok(false) => {
+            events.emit(
+                SecurityEvent::new(AuditAction::ConfirmPassword.name(), Outcome::Failure)
+                    .with_user(user_id),
+            );
+            VaultResponse::flash_error_redirect_to(
+                rocket::uri!(confirm(form.next)),
+                "The given password is wrong, please try again",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct AccessTokenContext {
+    token: AccessToken,
+    write: bool,
+    tables: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct AccessTokensContext {
+    flash: FlashContext,
+    tokens: Vec<AccessTokenContext>,
+    /// The tables that new tokens can be restricted to
+    tables: Vec<TableIndexEntry>,
+    /// The token that was just created, it is only shown once
+    new_token: Option<String>,
+}
+
+async fn fetch_access_tokens_context(
+    database: &VaultDb,
+    session: &SessionMetadata,
+    flash: FlashContext,
+) -> sqlx::Result<AccessTokensContext> {
+    let token_tables: Vec<AccessTokenTable> =
+        database.fetch_access_token_tables(session.user_id).await?;
+    let tokens = database
+        .fetch_access_tokens(session.user_id)
+        .await?
+        .into_iter()
+        .map(|token| AccessTokenContext {
+            write: token.permissions.contains(Permission::Write),
+            tables: token_tables
+                .iter()
+                .filter(|t| t.token_id == token.id)
+                .map(|t| t.ui_name.clone())
+                .collect(),
+            token,
+        })
+        .collect();
+    Ok(AccessTokensContext {
+        flash,
+        tokens,
+        tables: super::fetch_accessible_table_index(database, session).await?,
+        new_token: None,
+    })
+}
+
+#[rocket::get("/account/tokens")]
+async fn access_tokens(
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        let session = match session_manager
+            .lock()
+            .await
+            .get_session_metadata(token.token())
+        {
+            Some(session) => session,
+            None => return VaultResponse::Err(http::Status::Unauthorized),
+        };
+        let flash = FlashContext::default()
+            .with_general_context(
+                GeneralContext::from(config.inner())
+                    .with_permissions(session.permissions)
+                    .with_csrf_token(token.csrf_token()),
+            )
+            .with_optional_flash(flash);
+        match fetch_access_tokens_context(database, &session, flash).await {
+            Ok(context) => VaultResponse::Ok(templates::Template::render("access-tokens", context)),
+            Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+        }
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct NewAccessTokenData {
+    #[field(validate = len(1..=64))]
+    name: String,
+    /// Either `read` or `write`
+    scope: Permission,
+    /// Whether the token can decrypt values, which is a sensitive action
+    decrypt: bool,
+    /// `0` for tokens that never expire
+    expires_in_days: u32,
+    /// The tables the token is restricted to, all tables if none are given
+    tables: Vec<u64>,
+}
+
+/// Creates a new personal access token and shows it once
+#[rocket::post("/account/tokens", data = "<form>")]
+async fn create_access_token_submit(
+    auth: TokenAuth<WithCookie>,
+    form: CsrfForm<NewAccessTokenData>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<templates::Template> {
+    let session = match session_manager
+        .lock()
+        .await
+        .get_session_metadata(auth.token())
+    {
+        Some(session) => session,
+        None => return VaultResponse::Err(http::Status::Unauthorized),
+    };
+    if let Some(response) = super::check_confirmed(config, &session, rocket::uri!(access_tokens)) {
+        return response;
+    }
+    let permissions = match form.scope {
+        Permission::Read => Permissions::from_iter([Permission::Read]),
+        Permission::Write => Permissions::from_iter([Permission::Read, Permission::Write]),
+        _ => return VaultResponse::Err(http::Status::BadRequest),
+    };
+    //Tokens can only be restricted to tables the account can access itself
+    let index = match super::fetch_accessible_table_index(database, &session).await {
+        Ok(index) => index,
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    if !form.tables.iter().all(|t| index.iter().any(|e| e.id == *t)) {
+        return VaultResponse::Err(http::Status::BadRequest);
+    }
+    let mut tables = form.tables.clone();
+    tables.sort_unstable();
+    tables.dedup();
+    let expires = (form.expires_in_days > 0).then(|| {
+        chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0)
+            + chrono::Duration::days(form.expires_in_days as i64)
+    });
+    let token = sessions::gen_access_token();
+    let token_id = match database
+        .insert_access_token(
+            session.user_id,
+            &form.name,
+            &sessions::hash_access_token(&token),
+            permissions,
+            tables.is_empty(),
+            form.decrypt,
+            expires,
+        )
+        .await
+    {
+        Ok(result) => result.last_insert_id(),
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    for table_id in &tables {
+        if database
+            .insert_access_token_table(token_id, *table_id)
+            .await
+            .is_err()
+        {
+            return VaultResponse::Err(http::Status::InternalServerError);
+        }
+    }
+    super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::CreateAccessToken).with_details(format!(
+            "token {} ({}{})",
+            form.name,
+            form.scope.name(),
+            if form.decrypt { ", decrypt" } else { "" }
+        )),
+    )
+    .await;
+    let mut flash = FlashContext::default().with_general_context(
+        GeneralContext::from(config.inner())
+            .with_permissions(session.permissions)
+            .with_csrf_token(auth.csrf_token()),
+    );
+    flash.kind = Some("success".to_string());
+    flash.message =
+        Some("Created the token, copy it now because it won't be shown again".to_string());
+    match fetch_access_tokens_context(database, &session, flash).await {
+        Ok(mut context) => {
+            context.new_token = Some(token);
+            VaultResponse::Ok(templates::Template::render("access-tokens", context))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct RevokeAccessTokenData {
+    token_id: u64,
+}
+
+#[rocket::post("/account/tokens/revoke", data = "<form>")]
+async fn revoke_access_token_submit(
+    auth: TokenAuth<WithCookie>,
+    form: CsrfForm<RevokeAccessTokenData>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    let user_id = match session_manager
+        .lock()
+        .await
+        .get_session_metadata(auth.token())
+    {
+        Some(session) => session.user_id,
+        None => return VaultResponse::Err(http::Status::Unauthorized),
+    };
+    match database.delete_access_token(form.token_id, user_id).await {
+        Ok(result) if result.rows_affected() > 0 => {
+            session_manager
+                .lock()
+                .await
+                .revoke_access_token(form.token_id);
+            super::audit_user(
+                database,
+                events,
+                user_id,
+                AuditRecord::new(AuditAction::RevokeAccessToken)
+                    .with_details(format!("token id {}", form.token_id)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(access_tokens),
+                "Revoked the token",
+            )
+        }
+        Ok(_) => VaultResponse::Err(http::Status::NotFound),
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(body: &str) -> String {
+        let form = form::Form::<form::Contextual<'_, ChangePasswordData<'_>>>::parse(body).unwrap();
+        assert!(form.value.is_none());
+        change_password_error(&form.context)
+    }
+
+    #[test]
+    fn mismatched_passwords() {
+        assert_eq!(
+            error_of("current_password=a&password=b&password_confirm=c"),
+            "Both new passwords must match"
+        );
+    }
+
+    #[test]
+    fn missing_field() {
+        assert_eq!(
+            error_of("password=b&password_confirm=b"),
+            "The current password is missing"
+        );
+    }
+}
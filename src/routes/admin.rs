@@ -1,16 +1,21 @@
 //! Contains all routes and types of the admin page
 
+use crate::audit::{AuditAction, AuditRecord};
+use crate::csrf::CsrfForm;
 use crate::database::Password;
+use crate::events::EventSender;
 use crate::routes::{FlashContext, VaultResponse};
-use crate::sessions::{SafeSessionManager, TokenAuthResult, WithCookie};
+use crate::sessions::{SafeSessionManager, TokenAuth, TokenAuthResult, WithCookie};
 use crate::{templates, VaultConfig, VaultDb};
-use rocket::{form, http, request};
+use rocket::{http, request};
 
 pub fn get_routes() -> Vec<rocket::Route> {
     rocket::routes![
         admin,
         add_password,
         add_password_submit,
+        edit_password,
+        edit_password_submit,
         delete_password_submit
     ]
 }
@@ -51,6 +56,7 @@ async fn admin(
             let mut context = AdminContext::default().with_flash(
                 FlashContext::default()
                     .with_config(config)
+                    .with_csrf_token(token.csrf_token())
                     .with_optional_flash(flash),
             );
             if let Ok(passwords) = database.fetch_all_password(false).await {
@@ -83,6 +89,7 @@ async fn add_password(
                 "add-password",
                 FlashContext::default()
                     .with_config(config)
+                    .with_csrf_token(token.csrf_token())
                     .with_optional_flash(flash),
             ))
         } else {
@@ -103,9 +110,11 @@ struct AddPasswordData<'a> {
 #[rocket::post("/admin/add", data = "<form>")]
 async fn add_password_submit(
     auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
     session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
-    form: form::Form<AddPasswordData<'_>>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<AddPasswordData<'_>>,
 ) -> VaultResponse<()> {
     if let Ok(token) = auth {
         if session_manager
@@ -114,11 +123,28 @@ async fn add_password_submit(
             .is_admin_session(token.token())
             .unwrap_or(false)
         {
+            if let Err(violation) = config.password_policy.check(form.password, &[form.name]) {
+                return VaultResponse::flash_error_redirect_to(
+                    rocket::uri!(add_password),
+                    violation.to_string(),
+                );
+            }
             match database
                 .insert_password(form.name, form.password, form.admin)
                 .await
             {
-                Ok(_) => VaultResponse::redirect_to(rocket::uri!(admin)),
+                Ok(_) => {
+                    super::audit(
+                        database,
+                        events,
+                        session_manager,
+                        token.token(),
+                        AuditRecord::new(AuditAction::CreateAccount)
+                            .with_details(format!("account {}", form.name)),
+                    )
+                    .await;
+                    VaultResponse::redirect_to(rocket::uri!(admin))
+                }
                 Err(sqlx::Error::Database(e)) => {
                     VaultResponse::flash_error_redirect_to(rocket::uri!(add_password), e.message())
                 }
@@ -132,6 +158,149 @@ async fn add_password_submit(
     }
 }
 
+#[derive(serde::Serialize)]
+struct EditPasswordContext {
+    flash: FlashContext,
+    password: Password,
+}
+
+#[rocket::get("/admin/edit?<id>")]
+async fn edit_password(
+    id: u64,
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        if session_manager
+            .lock()
+            .await
+            .is_admin_session(token.token())
+            .unwrap_or(false)
+        {
+            match database.fetch_password_by_id(id).await {
+                Ok(Some(password)) => VaultResponse::Ok(templates::Template::render(
+                    "edit-password",
+                    EditPasswordContext {
+                        flash: FlashContext::default()
+                            .with_config(config)
+                            .with_csrf_token(token.csrf_token())
+                            .with_optional_flash(flash),
+                        password,
+                    },
+                )),
+                Ok(None) => VaultResponse::Err(http::Status::NotFound),
+                Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+            }
+        } else {
+            VaultResponse::Err(http::Status::Unauthorized)
+        }
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct EditPasswordData<'a> {
+    password_id: u64,
+    #[field(validate = len(1..=64))]
+    name: String,
+    admin: bool,
+    disabled: bool,
+    password_reset: bool,
+    /// Sets a new password if not empty
+    password: &'a str,
+}
+
+#[rocket::post("/admin/edit", data = "<form>")]
+async fn edit_password_submit(
+    auth: TokenAuth<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<EditPasswordData<'_>>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    let redirect = rocket::uri!(edit_password(form.password_id));
+    match database.fetch_password_by_id(form.password_id).await {
+        Ok(Some(p)) => {
+            if p.admin && !p.disabled && (!form.admin || form.disabled) {
+                match database.exists_other_admin(p.id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        return VaultResponse::flash_error_redirect_to(
+                            redirect,
+                            "The last admin account can not be demoted or disabled",
+                        )
+                    }
+                    Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+                }
+            }
+        }
+        Ok(None) => return VaultResponse::Err(http::Status::NotFound),
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    }
+    let password = Some(form.password).filter(|p| !p.is_empty());
+    if let Some(password) = password {
+        if let Err(violation) = config.password_policy.check(password, &[&form.name]) {
+            return VaultResponse::flash_error_redirect_to(redirect, violation.to_string());
+        }
+    }
+    match database
+        .update_account(
+            form.password_id,
+            &form.name,
+            form.admin,
+            form.disabled,
+            form.password_reset,
+            password,
+        )
+        .await
+    {
+        Ok(false) => VaultResponse::flash_error_redirect_to(
+            redirect,
+            "The new password can not be used, please choose a different one",
+        ),
+        Ok(true) => {
+            //The account's sessions still have the old permissions
+            session_manager
+                .lock()
+                .await
+                .revoke_user_sessions(form.password_id, Some(auth.token()));
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::EditAccount).with_details(format!(
+                    "account {}: admin={}, disabled={}, password_reset={}, new password={}",
+                    form.password_id,
+                    form.admin,
+                    form.disabled,
+                    form.password_reset,
+                    password.is_some()
+                )),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(redirect, "Saved account")
+        }
+        Err(sqlx::Error::Database(e)) => {
+            VaultResponse::flash_error_redirect_to(redirect, e.message())
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
 #[derive(rocket::FromForm)]
 struct DeletePasswordData {
     password_id: u64,
@@ -142,20 +311,50 @@ async fn delete_password_submit(
     auth: TokenAuthResult<WithCookie>,
     session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
-    form: form::Form<DeletePasswordData>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<DeletePasswordData>,
 ) -> VaultResponse<()> {
-    if let Ok(token) = auth {
+    if let Ok(auth) = auth {
         if session_manager
             .lock()
             .await
-            .is_admin_session(token.token())
+            .is_admin_session(auth.token())
             .unwrap_or(false)
         {
+            match database.fetch_password_by_id(form.password_id).await {
+                Ok(Some(p)) if p.admin => match database.exists_other_admin(p.id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        return VaultResponse::flash_error_redirect_to(
+                            rocket::uri!(admin),
+                            "The last admin account can not be deleted",
+                        )
+                    }
+                    Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+                },
+                Ok(_) => {}
+                Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+            }
             match database.delete_password(form.password_id).await {
-                Ok(_) => VaultResponse::flash_success_redirect_to(
-                    rocket::uri!(admin),
-                    "Deleted password",
-                ),
+                Ok(_) => {
+                    session_manager
+                        .lock()
+                        .await
+                        .revoke_user_sessions(form.password_id, Some(auth.token()));
+                    super::audit(
+                        database,
+                        events,
+                        session_manager,
+                        auth.token(),
+                        AuditRecord::new(AuditAction::DeleteAccount)
+                            .with_details(format!("account {}", form.password_id)),
+                    )
+                    .await;
+                    VaultResponse::flash_success_redirect_to(
+                        rocket::uri!(admin),
+                        "Deleted password",
+                    )
+                }
                 Err(sqlx::Error::Database(e)) => {
                     VaultResponse::flash_error_redirect_to(rocket::uri!(admin), e.message())
                 }
@@ -0,0 +1,183 @@
+//! Contains the versioned JSON API, which is mounted at `/api/v1`
+//!
+//! Every route authenticates with an `Authorization: Bearer <token>` header and answers with JSON, errors included.
+//! The token is obtained from `POST /api/v1/session`.
+
+use crate::permissions::Permission;
+use crate::sessions::{SafeSessionManager, SessionMetadata};
+use crate::{VaultConfig, VaultDb};
+use rocket::serde::json::Json;
+use rocket::{http, request, response};
+
+pub mod openapi;
+pub mod session;
+pub mod tables;
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    let mut routes = session::get_routes();
+    routes.extend(tables::get_routes());
+    routes
+}
+
+pub fn get_operations() -> Vec<openapi::Operation> {
+    let mut operations = session::get_operations();
+    operations.extend(tables::get_operations());
+    operations
+}
+
+pub fn get_catchers() -> Vec<rocket::Catcher> {
+    rocket::catchers![default_catcher]
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// An error response of the API, serialized as `{"status": 404, "error": "Not Found", "message": "..."}`
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct ApiError {
+    #[serde(serialize_with = "serialize_status")]
+    #[schemars(with = "u16")]
+    status: http::Status,
+    error: &'static str,
+    message: String,
+    /// Where the session can confirm its password again, only set if the action requires a recent confirmation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirm_uri: Option<String>,
+}
+
+fn serialize_status<S: serde::Serializer>(status: &http::Status, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u16(status.code)
+}
+
+impl ApiError {
+    fn new<M: Into<String>>(status: http::Status, message: M) -> Self {
+        Self {
+            status,
+            error: status.reason().unwrap_or("Unknown"),
+            message: message.into(),
+            confirm_uri: None,
+        }
+    }
+
+    fn bad_request<M: Into<String>>(message: M) -> Self {
+        Self::new(http::Status::BadRequest, message)
+    }
+
+    fn unauthorized() -> Self {
+        Self::new(
+            http::Status::Unauthorized,
+            "A valid bearer token is required",
+        )
+    }
+
+    fn forbidden<M: Into<String>>(message: M) -> Self {
+        Self::new(http::Status::Forbidden, message)
+    }
+
+    /// The session has to confirm its password with `POST /api/v1/session/confirm` before the action
+    fn confirmation_required(action: &str) -> Self {
+        Self {
+            confirm_uri: Some(rocket::uri!("/api/v1", session::confirm_session).to_string()),
+            ..Self::forbidden(format!(
+                "{} requires a recent confirmation, please confirm the session again",
+                action
+            ))
+        }
+    }
+
+    fn not_found<M: Into<String>>(message: M) -> Self {
+        Self::new(http::Status::NotFound, message)
+    }
+
+    fn unprocessable<M: Into<String>>(message: M) -> Self {
+        Self::new(http::Status::UnprocessableEntity, message)
+    }
+
+    fn internal() -> Self {
+        Self::new(
+            http::Status::InternalServerError,
+            "An internal error occurred",
+        )
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(_: sqlx::Error) -> Self {
+        //The error itself is already logged by the database
+        Self::internal()
+    }
+}
+
+impl<'r> response::Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r request::Request<'_>) -> response::Result<'static> {
+        let status = self.status;
+        response::Response::build_from(Json(self).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// Answers all failures of guards (for example a missing token or an invalid body) with a JSON error
+#[rocket::catch(default)]
+fn default_catcher(status: http::Status, _request: &request::Request) -> ApiError {
+    match status.code {
+        401 => ApiError::unauthorized(),
+        400 => ApiError::bad_request("The request is malformed"),
+        422 => ApiError::unprocessable("The request body doesn't have the expected format"),
+        404 => ApiError::not_found("There is no such route"),
+        _ => ApiError::new(status, status.reason().unwrap_or("Unknown")),
+    }
+}
+
+/// Returns the session of the given token
+async fn fetch_session(
+    session_manager: &SafeSessionManager,
+    token: &str,
+) -> Result<SessionMetadata, ApiError> {
+    session_manager
+        .lock()
+        .await
+        .get_session_metadata(token)
+        .ok_or_else(ApiError::unauthorized)
+}
+
+/// Makes sure that the given session recently confirmed its password, `action` names the sensitive action
+fn check_confirmed(
+    config: &VaultConfig,
+    session: &SessionMetadata,
+    action: &str,
+) -> Result<(), ApiError> {
+    if session.is_confirmed_within(std::time::Duration::from_secs(config.sudo_duration_secs)) {
+        Ok(())
+    } else if session.access_token.is_some() {
+        Err(ApiError::forbidden(format!(
+            "{} isn't in the scope of this personal access token",
+            action
+        )))
+    } else {
+        Err(ApiError::confirmation_required(action))
+    }
+}
+
+/// Makes sure that the session with the given token has the given permission on the given table
+///
+/// Tables without read access are treated as if they would not exist.
+async fn check_table_permission(
+    database: &VaultDb,
+    session_manager: &SafeSessionManager,
+    token: &str,
+    table_id: u64,
+    permission: Permission,
+) -> Result<SessionMetadata, ApiError> {
+    let session = fetch_session(session_manager, token).await?;
+    let permissions = super::fetch_table_permissions(database, &session, table_id).await?;
+    if !permissions.contains(Permission::Read) {
+        Err(ApiError::not_found("The table doesn't exist"))
+    } else if !permissions.contains(permission) {
+        Err(ApiError::forbidden(format!(
+            "The {} permission is required",
+            permission.name()
+        )))
+    } else {
+        Ok(session)
+    }
+}
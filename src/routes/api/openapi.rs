@@ -0,0 +1,256 @@
+//! Contains the OpenAPI 3 document of the JSON API and the page that documents it
+//!
+//! The paths, methods and parameters are taken from the routes that are mounted at `/api/v1`. Every API module
+//! describes its routes in a `get_operations` function next to `get_routes`, the schemas of request and response
+//! bodies are derived from their types.
+
+use super::ApiError;
+use crate::routes::FlashContext;
+use crate::{templates, VaultConfig};
+use rocket::http::{self, ContentType};
+use rocket::serde::json::serde_json::Map;
+use rocket::serde::json::{json, Value};
+use rocket::{fairing, Phase, Rocket};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+
+/// The base path of the documented routes
+const API_BASE: &str = "/api/v1";
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![openapi_json, api_docs]
+}
+
+/// Generates the OpenAPI document once all routes are mounted
+pub fn fairing() -> impl fairing::Fairing {
+    fairing::AdHoc::on_ignite("OpenAPI", |rocket| async {
+        let document = OpenApiDocument::generate(&rocket, super::get_operations());
+        rocket.manage(document)
+    })
+}
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn schema_of<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+struct Param {
+    name: &'static str,
+    description: &'static str,
+    required: bool,
+    schema: SchemaFn,
+}
+
+/// Describes an API route, the route is matched by the name of its handler
+pub struct Operation {
+    name: &'static str,
+    summary: &'static str,
+    description: Option<&'static str>,
+    params: Vec<Param>,
+    body: Option<SchemaFn>,
+    status: http::Status,
+    response: Option<SchemaFn>,
+    public: bool,
+}
+
+impl Operation {
+    pub fn new(name: &'static str, summary: &'static str) -> Self {
+        Self {
+            name,
+            summary,
+            description: None,
+            params: vec![],
+            body: None,
+            status: http::Status::Ok,
+            response: None,
+            public: false,
+        }
+    }
+
+    pub fn with_description(mut self, description: &'static str) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    /// Describes a path or query parameter of the route, undescribed parameters are strings
+    pub fn with_param<T: JsonSchema>(
+        mut self,
+        name: &'static str,
+        description: &'static str,
+    ) -> Self {
+        self.params.push(Param {
+            name,
+            description,
+            required: true,
+            schema: schema_of::<T>,
+        });
+        self
+    }
+
+    /// Describes a query parameter that can be left out
+    pub fn with_optional_param<T: JsonSchema>(
+        mut self,
+        name: &'static str,
+        description: &'static str,
+    ) -> Self {
+        self.params.push(Param {
+            name,
+            description,
+            required: false,
+            schema: schema_of::<T>,
+        });
+        self
+    }
+
+    /// Sets the type of the JSON request body
+    pub fn with_body<T: JsonSchema>(mut self) -> Self {
+        self.body = Some(schema_of::<T>);
+        self
+    }
+
+    /// Sets the status and the type of the JSON response body of a successful request
+    pub fn with_response<T: JsonSchema>(mut self, status: http::Status) -> Self {
+        self.status = status;
+        self.response = Some(schema_of::<T>);
+        self
+    }
+
+    /// Sets the status of a successful request that doesn't return a body
+    pub fn with_empty_response(mut self, status: http::Status) -> Self {
+        self.status = status;
+        self.response = None;
+        self
+    }
+
+    /// Marks the route as usable without a bearer token
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    fn to_json(&self, route: &rocket::Route, gen: &mut SchemaGenerator) -> Value {
+        let mut params = vec![];
+        let path_params = route.uri.path().split('/');
+        let query_params = route.uri.query().unwrap_or_default().split('&');
+        for (location, segment) in path_params
+            .map(|s| ("path", s))
+            .chain(query_params.map(|s| ("query", s)))
+        {
+            let name = match segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+                Some(name) => name.trim_end_matches(".."),
+                None => continue,
+            };
+            let (description, required, schema) = match self.params.iter().find(|p| p.name == name)
+            {
+                Some(param) => (param.description, param.required, (param.schema)(gen)),
+                None => ("", false, schema_of::<String>(gen)),
+            };
+            params.push(json!({
+                "name": name,
+                "in": location,
+                "required": required || location == "path",
+                "description": description,
+                "schema": schema,
+            }));
+        }
+
+        let mut responses = Map::new();
+        let mut success = json!({ "description": self.status.reason().unwrap_or("Success") });
+        if let Some(response) = self.response {
+            success["content"] = json!({ "application/json": { "schema": response(gen) } });
+        }
+        responses.insert(self.status.code.to_string(), success);
+        responses.insert(
+            "default".to_string(),
+            json!({
+                "description": "An error",
+                "content": { "application/json": { "schema": schema_of::<ApiError>(gen) } },
+            }),
+        );
+
+        let mut operation = json!({
+            "operationId": route.name,
+            "summary": self.summary,
+            "parameters": params,
+            "responses": responses,
+        });
+        if let Some(description) = self.description {
+            operation["description"] = json!(description);
+        }
+        if let Some(body) = self.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": body(gen) } },
+            });
+        }
+        if self.public {
+            operation["security"] = json!([]);
+        }
+        operation
+    }
+}
+
+/// The generated OpenAPI document, serialized once at ignition
+pub struct OpenApiDocument(String);
+
+impl OpenApiDocument {
+    fn generate<P: Phase>(rocket: &Rocket<P>, operations: Vec<Operation>) -> Self {
+        let title = rocket
+            .state::<VaultConfig>()
+            .map(|c| format!("{} API", c.name))
+            .unwrap_or_else(|| "Password Vault API".to_string());
+        let mut gen = SchemaSettings::openapi3().into_generator();
+        let mut paths = Map::new();
+        for route in rocket.routes().filter(|r| r.uri.base() == API_BASE) {
+            let name = route.name.as_deref().unwrap_or_default();
+            let operation = match operations.iter().find(|o| o.name == name) {
+                Some(operation) => operation.to_json(route, &mut gen),
+                None => {
+                    rocket::warn!("The API route {} is not documented", route);
+                    Operation::new("", "Undocumented").to_json(route, &mut gen)
+                }
+            };
+            //OpenAPI writes path parameters as `{id}` instead of `<id>`
+            let path = route.uri.path().replace('<', "{").replace('>', "}");
+            let item = paths.entry(path).or_insert_with(|| json!({}));
+            item[route.method.as_str().to_lowercase()] = operation;
+        }
+        let document = json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": title,
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": paths,
+            "components": {
+                "schemas": gen.take_definitions(),
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer" },
+                },
+            },
+            "security": [{ "bearer": [] }],
+        });
+        Self(document.to_string())
+    }
+}
+
+#[rocket::get("/openapi.json")]
+fn openapi_json(document: &rocket::State<OpenApiDocument>) -> (ContentType, String) {
+    (ContentType::JSON, document.0.clone())
+}
+
+#[derive(serde::Serialize)]
+struct ApiDocsContext {
+    flash: FlashContext,
+}
+
+/// Renders the document, so it can be explored and tried out in the browser
+#[rocket::get("/docs")]
+fn api_docs(config: &rocket::State<VaultConfig>) -> templates::Template {
+    let context = ApiDocsContext {
+        flash: FlashContext::default().with_config(config),
+    };
+    templates::Template::render("api-docs", context)
+}
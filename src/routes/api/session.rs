@@ -0,0 +1,211 @@
+//! Contains the API routes that exchange credentials for a bearer token and revoke it again
+
+use super::openapi::Operation;
+use super::ApiError;
+use crate::audit::{AuditAction, AuditRecord};
+use crate::auth::{AuthError, Authenticator};
+use crate::events::{EventSender, Outcome, SecurityEvent};
+use crate::sessions::{SafeSessionManager, TokenAuth, WithHeader};
+use crate::{VaultConfig, VaultDb};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use std::net::IpAddr;
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![create_session, confirm_session, delete_session]
+}
+
+pub fn get_operations() -> Vec<Operation> {
+    vec![
+        Operation::new("create_session", "Logs in and returns a new session token")
+            .with_description(
+                "The session counts as confirmed for `sudo_duration_secs` (15 minutes by default), which is shorter \
+                than its validity. Afterwards, decrypting and deleting tables are refused with a `confirm_uri` until \
+                the session is confirmed again.",
+            )
+            .with_body::<Credentials>()
+            .with_response::<SessionToken>(rocket::http::Status::Created)
+            .public(),
+        Operation::new(
+            "confirm_session",
+            "Confirms the password of the session, so it can take sensitive actions again",
+        )
+        .with_body::<Confirmation>()
+        .with_empty_response(rocket::http::Status::NoContent),
+        Operation::new("delete_session", "Revokes the session of the given token")
+            .with_empty_response(rocket::http::Status::NoContent),
+    ]
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct Credentials {
+    /// Only required for accounts of a directory service
+    username: Option<String>,
+    password: String,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct Confirmation {
+    password: String,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+struct SessionToken {
+    token: String,
+    expires: chrono::DateTime<chrono::Utc>,
+    expires_in_secs: u64,
+}
+
+/// Starts a new session, just like the login page does, and returns its token
+///
+/// The session counts as confirmed, but only for `sudo_duration_secs`. Afterwards it has to be confirmed again with
+/// [`confirm_session`] before sensitive actions.
+#[rocket::post("/session", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn create_session(
+    body: Json<Credentials>,
+    client_ip: Option<IpAddr>,
+    config: &rocket::State<VaultConfig>,
+    authenticator: &rocket::State<Authenticator>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::Created<Json<SessionToken>>, ApiError> {
+    let username = body.username.as_deref().filter(|u| !u.is_empty());
+    let account = match authenticator
+        .authenticate(database, username, &body.password)
+        .await
+    {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            events.emit(
+                SecurityEvent::new(AuditAction::Login.name(), Outcome::Failure)
+                    .with_client_ip(client_ip)
+                    .with_details("wrong password via api"),
+            );
+            return Err(ApiError::new(
+                rocket::http::Status::Unauthorized,
+                "The given credentials are wrong",
+            ));
+        }
+        Err(AuthError::NameTaken(_)) => {
+            return Err(ApiError::forbidden(
+                "A local account with the same name already exists, please contact an admin",
+            ))
+        }
+        Err(_) => return Err(ApiError::internal()),
+    };
+    if account.disabled {
+        events.emit(
+            SecurityEvent::new(AuditAction::Login.name(), Outcome::Failure)
+                .with_user(account.id)
+                .with_client_ip(client_ip)
+                .with_details("account disabled"),
+        );
+        return Err(ApiError::forbidden("This account is disabled"));
+    }
+    //The password can only be changed on the account page
+    if account.password_reset {
+        return Err(ApiError::forbidden(
+            "The password of this account has to be changed on the website first",
+        ));
+    }
+    let permissions = database
+        .fetch_permissions(&account, &config.default_role)
+        .await?;
+    let validity = std::time::Duration::from_secs(config.token_validity_duration_secs);
+    let (token, _) = session_manager.lock().await.generate_session(
+        config.token_length as usize,
+        validity,
+        account.id,
+        permissions,
+    );
+    let mut details = vec!["via api".to_string()];
+    if let Some(ip) = client_ip {
+        details.push(format!("from {}", ip));
+    }
+    super::super::audit_user(
+        database,
+        events,
+        account.id,
+        AuditRecord::new(AuditAction::Login).with_details(details.join(" ")),
+    )
+    .await;
+    let expires = chrono::Utc::now()
+        + chrono::Duration::from_std(validity).map_err(|_| ApiError::internal())?;
+    Ok(
+        status::Created::new(rocket::uri!("/api/v1", delete_session).to_string()).body(Json(
+            SessionToken {
+                token,
+                expires,
+                expires_in_secs: config.token_validity_duration_secs,
+            },
+        )),
+    )
+}
+
+/// Confirms the password of the session, just like the confirmation page does
+///
+/// Personal access tokens can't be confirmed, they don't belong to a login.
+#[rocket::post("/session/confirm", data = "<body>")]
+async fn confirm_session(
+    body: Json<Confirmation>,
+    auth: TokenAuth<WithHeader>,
+    authenticator: &rocket::State<Authenticator>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::NoContent, ApiError> {
+    let session = super::fetch_session(session_manager, auth.token()).await?;
+    if session.access_token.is_some() {
+        return Err(ApiError::forbidden(
+            "Personal access tokens can't be confirmed, their scope decides about decrypting",
+        ));
+    }
+    let account = database
+        .fetch_password_by_id(session.user_id)
+        .await?
+        .ok_or_else(ApiError::unauthorized)?;
+    if authenticator
+        .verify(database, &account, &body.password)
+        .await
+        .map_err(|_| ApiError::internal())?
+    {
+        session_manager.lock().await.confirm_session(auth.token());
+        super::super::audit_user(
+            database,
+            events,
+            session.user_id,
+            AuditRecord::new(AuditAction::ConfirmPassword).with_details("via api"),
+        )
+        .await;
+        Ok(status::NoContent)
+    } else {
+        events.emit(
+            SecurityEvent::new(AuditAction::ConfirmPassword.name(), Outcome::Failure)
+                .with_user(session.user_id)
+                .with_details("via api"),
+        );
+        Err(ApiError::forbidden("The given password is wrong"))
+    }
+}
+
+/// Revokes the session of the given token
+#[rocket::delete("/session")]
+async fn delete_session(
+    auth: TokenAuth<WithHeader>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::NoContent, ApiError> {
+    let session = super::fetch_session(session_manager, auth.token()).await?;
+    session_manager.lock().await.revoke_session(auth.token());
+    super::super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::Logout).with_details("via api"),
+    )
+    .await;
+    Ok(status::NoContent)
+}
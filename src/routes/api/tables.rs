@@ -0,0 +1,463 @@
+//! Contains the API routes of tables and their rows
+
+use super::openapi::Operation;
+use super::{ApiError, ApiResult};
+use crate::audit::{AuditAction, AuditRecord};
+use crate::database::{ColumnIndexEntry, TableIndexEntry, TableRow, VaultTable};
+use crate::events::EventSender;
+use crate::permissions::Permission;
+use crate::sessions::{SafeSessionManager, TokenAuth, WithAccessToken};
+use crate::{crypt, VaultConfig, VaultDb};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use std::collections;
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        list_tables,
+        create_table,
+        table,
+        delete_table,
+        add_row,
+        update_row,
+        delete_row,
+        search
+    ]
+}
+
+pub fn get_operations() -> Vec<Operation> {
+    use rocket::http::Status;
+    vec![
+        Operation::new("list_tables", "Lists all tables the account can access")
+            .with_response::<Vec<TableIndexEntry>>(Status::Ok),
+        Operation::new("create_table", "Creates a new table")
+            .with_description("The key and password columns are named `Key` and `Password` by default.")
+            .with_body::<NewTable>()
+            .with_response::<VaultTable>(Status::Created),
+        Operation::new("table", "Returns a table with all rows that match the query")
+            .with_description(
+                "Encrypted cells are empty, unless `decrypt` is set. Decrypting requires a recent confirmation \
+                 and is recorded in the audit log.",
+            )
+            .with_param::<u64>("id", "The id of the table")
+            .with_optional_param::<String>("q", "Only returns rows whose key contains the query")
+            .with_optional_param::<bool>("decrypt", "Returns the plain values of encrypted cells")
+            .with_response::<VaultTable>(Status::Ok),
+        Operation::new("delete_table", "Deletes a table with all its rows")
+            .with_description("Requires a recent confirmation.")
+            .with_param::<u64>("id", "The id of the table")
+            .with_empty_response(Status::NoContent),
+        Operation::new("add_row", "Adds a row to a table")
+            .with_description(
+                "The body maps the column names to the plain values, all required columns have to be given.",
+            )
+            .with_param::<u64>("table_id", "The id of the table")
+            .with_body::<RowData>()
+            .with_response::<TableRow>(Status::Created),
+        Operation::new("update_row", "Updates the given cells of a row")
+            .with_description("All other cells are left unchanged.")
+            .with_param::<u64>("table_id", "The id of the table")
+            .with_param::<u64>("row_id", "The id of the row")
+            .with_body::<RowData>()
+            .with_response::<TableRow>(Status::Ok),
+        Operation::new("delete_row", "Deletes a row")
+            .with_param::<u64>("table_id", "The id of the table")
+            .with_param::<u64>("row_id", "The id of the row")
+            .with_empty_response(Status::NoContent),
+        Operation::new("search", "Searches the keys of all readable tables")
+            .with_description("Only tables with matching rows are returned, encrypted cells are empty.")
+            .with_param::<String>("q", "The query")
+            .with_response::<Vec<VaultTable>>(Status::Ok),
+    ]
+}
+
+/// The cells of a row, mapped from the column name to the plain value
+type RowData = collections::HashMap<String, String>;
+
+#[rocket::get("/tables")]
+async fn list_tables(
+    auth: TokenAuth<WithAccessToken>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+) -> ApiResult<Vec<TableIndexEntry>> {
+    let session = super::fetch_session(session_manager, auth.token()).await?;
+    if !session.permissions.contains(Permission::Read) {
+        return Err(ApiError::forbidden("The read permission is required"));
+    }
+    Ok(Json(
+        super::super::fetch_accessible_table_index(database, &session).await?,
+    ))
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct NewTable {
+    name: String,
+    key_column: Option<String>,
+    password_column: Option<String>,
+    #[serde(default)]
+    extra_columns: Vec<String>,
+}
+
+#[rocket::post("/tables", data = "<body>")]
+async fn create_table(
+    auth: TokenAuth<WithAccessToken>,
+    body: Json<NewTable>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::Created<Json<VaultTable>>, ApiError> {
+    let session = super::fetch_session(session_manager, auth.token()).await?;
+    if !session.permissions.contains(Permission::ManageTables) {
+        return Err(ApiError::forbidden(
+            "The manage_tables permission is required",
+        ));
+    }
+    if body.name.is_empty() {
+        return Err(ApiError::unprocessable("The name must not be empty"));
+    }
+    let extra: Vec<&str> = body.extra_columns.iter().map(|c| c.as_str()).collect();
+    let table_id = match database
+        .create_vault_table(
+            &body.name,
+            body.key_column.as_deref().unwrap_or("Key"),
+            body.password_column.as_deref().unwrap_or("Password"),
+            &extra,
+        )
+        .await
+    {
+        Ok(id) => id,
+        Err(sqlx::Error::Database(e)) => return Err(ApiError::unprocessable(e.message())),
+        Err(e) => return Err(e.into()),
+    };
+    super::super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::CreateTable)
+            .with_table(table_id)
+            .with_details(format!("table {}", body.name)),
+    )
+    .await;
+    let table = database
+        .fetch_table(table_id, &None)
+        .await?
+        .ok_or_else(ApiError::internal)?;
+    Ok(
+        status::Created::new(rocket::uri!("/api/v1", table(table_id, _, _)).to_string())
+            .body(Json(table)),
+    )
+}
+
+/// Returns a table with all rows that match the query
+///
+/// Encrypted cells are empty, unless `decrypt` is set. Decrypting requires a recent confirmation and is recorded in
+/// the audit log.
+#[rocket::get("/tables/<id>?<q>&<decrypt>")]
+#[allow(clippy::too_many_arguments)]
+async fn table(
+    id: u64,
+    q: Option<String>,
+    decrypt: Option<bool>,
+    auth: TokenAuth<WithAccessToken>,
+    config: &rocket::State<VaultConfig>,
+    keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> ApiResult<VaultTable> {
+    let session = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        id,
+        Permission::Read,
+    )
+    .await?;
+    let mut table = database
+        .fetch_table(id, &q)
+        .await?
+        .ok_or_else(|| ApiError::not_found("The table doesn't exist"))?;
+    if decrypt.unwrap_or(false) {
+        super::check_confirmed(config, &session, "Decrypting")?;
+        //Nothing is decrypted if the reveal can't be recorded
+        super::super::audit_before(
+            database,
+            events,
+            session.user_id,
+            AuditRecord::new(AuditAction::Reveal)
+                .with_table(id)
+                .with_details(format!("{} rows via api", table.rows.len())),
+        )
+        .await?;
+        table.decrypt(keypair);
+    } else {
+        table.hide_encrypted();
+    }
+    Ok(Json(table))
+}
+
+#[rocket::delete("/tables/<id>")]
+async fn delete_table(
+    id: u64,
+    auth: TokenAuth<WithAccessToken>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::NoContent, ApiError> {
+    let session = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        id,
+        Permission::ManageTables,
+    )
+    .await?;
+    super::check_confirmed(config, &session, "Deleting a table")?;
+    if database.fetch_table_index_entry(id).await?.is_none() {
+        return Err(ApiError::not_found("The table doesn't exist"));
+    }
+    database.delete_vault_table(id).await?;
+    super::super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::DeleteTable).with_table(id),
+    )
+    .await;
+    Ok(status::NoContent)
+}
+
+/// Maps the given cells to the columns of the table and encrypts the values of encrypted columns
+///
+/// If `complete` is set, all required columns have to be given.
+fn prepare_cells<'a>(
+    index: &'a [ColumnIndexEntry],
+    data: &RowData,
+    complete: bool,
+    keypair: &crypt::KeyPair,
+) -> Result<collections::HashMap<&'a str, String>, ApiError> {
+    if let Some(unknown) = data
+        .keys()
+        .find(|k| !index.iter().any(|c| &c.column_name == *k))
+    {
+        return Err(ApiError::unprocessable(format!(
+            "The column {} doesn't exist",
+            unknown
+        )));
+    }
+    let mut cells = collections::HashMap::new();
+    for column in index {
+        match data.get(&column.column_name) {
+            Some(value) if column.required && value.is_empty() => {
+                return Err(ApiError::unprocessable(format!(
+                    "The column {} must not be empty",
+                    column.column_name
+                )))
+            }
+            Some(value) => {
+                let value = if column.encrypted {
+                    keypair
+                        .encrypt_string_to_hex(value)
+                        .map_err(|_| ApiError::internal())?
+                } else {
+                    value.clone()
+                };
+                cells.insert(column.column_name.as_str(), value);
+            }
+            None if complete && column.required => {
+                return Err(ApiError::unprocessable(format!(
+                    "The column {} is required",
+                    column.column_name
+                )))
+            }
+            None => {}
+        }
+    }
+    if cells.is_empty() {
+        return Err(ApiError::unprocessable("At least one column is required"));
+    }
+    Ok(cells)
+}
+
+#[rocket::post("/tables/<table_id>/rows", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn add_row(
+    table_id: u64,
+    auth: TokenAuth<WithAccessToken>,
+    body: Json<RowData>,
+    keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::Created<Json<TableRow>>, ApiError> {
+    let session = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        table_id,
+        Permission::Write,
+    )
+    .await?;
+    let index = database.fetch_column_index_by_id(table_id).await?;
+    if index.is_empty() {
+        return Err(ApiError::not_found("The table doesn't exist"));
+    }
+    let cells = prepare_cells(&index, &body, true, keypair)?;
+    let row_id = database
+        .insert_vault_data(
+            table_id,
+            cells.iter().map(|c| (*c.0, c.1.as_str())).collect(),
+        )
+        .await?
+        .last_insert_id();
+    super::super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::AddRow)
+            .with_table(table_id)
+            .with_row(row_id),
+    )
+    .await;
+    let mut row = database
+        .fetch_row(table_id, row_id)
+        .await?
+        .ok_or_else(ApiError::internal)?;
+    hide_encrypted(&mut row);
+    Ok(
+        status::Created::new(rocket::uri!("/api/v1", table(table_id, _, _)).to_string())
+            .body(Json(row)),
+    )
+}
+
+/// Updates the given cells of a row, all other cells are left unchanged
+#[rocket::put("/tables/<table_id>/rows/<row_id>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn update_row(
+    table_id: u64,
+    row_id: u64,
+    auth: TokenAuth<WithAccessToken>,
+    body: Json<RowData>,
+    keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> ApiResult<TableRow> {
+    let session = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        table_id,
+        Permission::Write,
+    )
+    .await?;
+    let index = database.fetch_column_index_by_id(table_id).await?;
+    if index.is_empty() {
+        return Err(ApiError::not_found("The table doesn't exist"));
+    }
+    if database.fetch_row(table_id, row_id).await?.is_none() {
+        return Err(ApiError::not_found("The row doesn't exist"));
+    }
+    let cells = prepare_cells(&index, &body, false, keypair)?;
+    let columns = cells.keys().copied().collect::<Vec<&str>>().join(", ");
+    database
+        .update_vault_row(
+            table_id,
+            row_id,
+            cells.iter().map(|c| (*c.0, c.1.as_str())).collect(),
+        )
+        .await?;
+    super::super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::UpdateRow)
+            .with_table(table_id)
+            .with_row(row_id)
+            .with_details(format!("columns {}", columns)),
+    )
+    .await;
+    let mut row = database
+        .fetch_row(table_id, row_id)
+        .await?
+        .ok_or_else(ApiError::internal)?;
+    hide_encrypted(&mut row);
+    Ok(Json(row))
+}
+
+#[rocket::delete("/tables/<table_id>/rows/<row_id>")]
+async fn delete_row(
+    table_id: u64,
+    row_id: u64,
+    auth: TokenAuth<WithAccessToken>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<status::NoContent, ApiError> {
+    let session = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        table_id,
+        Permission::Write,
+    )
+    .await?;
+    if database
+        .delete_vault_row(table_id, row_id)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(ApiError::not_found("The row doesn't exist"));
+    }
+    super::super::audit_user(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::DeleteRow)
+            .with_table(table_id)
+            .with_row(row_id),
+    )
+    .await;
+    Ok(status::NoContent)
+}
+
+/// Searches the keys of all readable tables and returns the tables with matching rows
+#[rocket::get("/search?<q>")]
+async fn search(
+    q: String,
+    auth: TokenAuth<WithAccessToken>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+) -> ApiResult<Vec<VaultTable>> {
+    let session = super::fetch_session(session_manager, auth.token()).await?;
+    if !session.permissions.contains(Permission::Read) {
+        return Err(ApiError::forbidden("The read permission is required"));
+    }
+    let query = Some(q);
+    let mut tables = vec![];
+    for entry in super::super::fetch_accessible_table_index(database, &session).await? {
+        let permissions =
+            super::super::fetch_table_permissions(database, &session, entry.id).await?;
+        if !permissions.contains(Permission::Read) {
+            continue;
+        }
+        if let Some(mut table) = database.fetch_table(entry.id, &query).await? {
+            if !table.rows.is_empty() {
+                table.hide_encrypted();
+                tables.push(table);
+            }
+        }
+    }
+    Ok(Json(tables))
+}
+
+/// Removes the data of all encrypted cells of a single row
+fn hide_encrypted(row: &mut TableRow) {
+    for cell in &mut row.cells {
+        if cell.encrypted {
+            cell.data.clear();
+        }
+    }
+}
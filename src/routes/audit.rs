@@ -0,0 +1,131 @@
+//! Contains all routes of the audit log page
+
+use crate::audit::{self, AuditAction};
+use crate::database::AuditLogEntry;
+use crate::routes::{FlashContext, VaultResponse};
+use crate::sessions::{SafeSessionManager, TokenAuthResult, WithCookie};
+use crate::{templates, VaultConfig, VaultDb};
+use rocket::{http, request};
+
+/// The maximum number of entries that are shown at once
+const MAX_ENTRIES: u32 = 500;
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![audit_log, verify]
+}
+
+#[derive(serde::Serialize)]
+struct FilterOption {
+    value: String,
+    name: String,
+    selected: bool,
+}
+
+#[derive(serde::Serialize)]
+struct AuditContext {
+    flash: FlashContext,
+    entries: Vec<AuditLogEntry>,
+    actions: Vec<FilterOption>,
+    accounts: Vec<FilterOption>,
+    table: Option<u64>,
+}
+
+#[rocket::get("/admin/audit?<action>&<user>&<table>")]
+#[allow(clippy::too_many_arguments)]
+async fn audit_log(
+    action: Option<AuditAction>,
+    user: Option<u64>,
+    table: Option<u64>,
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        if session_manager
+            .lock()
+            .await
+            .is_admin_session(token.token())
+            .unwrap_or(false)
+        {
+            match (
+                database
+                    .fetch_audit_log(action, user, table, MAX_ENTRIES)
+                    .await,
+                database.fetch_all_password(false).await,
+            ) {
+                (Ok(entries), Ok(passwords)) => VaultResponse::Ok(templates::Template::render(
+                    "audit",
+                    AuditContext {
+                        flash: FlashContext::default()
+                            .with_config(config)
+                            .with_csrf_token(token.csrf_token())
+                            .with_optional_flash(flash),
+                        entries,
+                        actions: AuditAction::ALL
+                            .iter()
+                            .map(|a| FilterOption {
+                                value: a.name().to_string(),
+                                name: a.name().replace('_', " "),
+                                selected: action == Some(*a),
+                            })
+                            .collect(),
+                        accounts: passwords
+                            .into_iter()
+                            .map(|p| FilterOption {
+                                value: p.id.to_string(),
+                                name: p.name,
+                                selected: user == Some(p.id),
+                            })
+                            .collect(),
+                        table,
+                    },
+                )),
+                _ => VaultResponse::Err(http::Status::InternalServerError),
+            }
+        } else {
+            VaultResponse::Err(http::Status::Unauthorized)
+        }
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[rocket::get("/admin/audit/verify")]
+async fn verify(
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+) -> VaultResponse<()> {
+    if let Ok(token) = auth {
+        if !session_manager
+            .lock()
+            .await
+            .is_admin_session(token.token())
+            .unwrap_or(false)
+        {
+            return VaultResponse::Err(http::Status::Unauthorized);
+        }
+        let redirect = rocket::uri!(audit_log(_, _, _));
+        match database.fetch_full_audit_log().await {
+            Ok(entries) => match audit::verify(config.audit_log_key.as_bytes(), &entries) {
+                Ok(_) => VaultResponse::flash_success_redirect_to(
+                    redirect,
+                    format!("The audit log is intact ({} entries)", entries.len()),
+                ),
+                Err(id) => VaultResponse::flash_error_redirect_to(
+                    redirect,
+                    format!(
+                        "The audit log was tampered with, entry {} or the entry before it was changed or removed",
+                        id
+                    ),
+                ),
+            },
+            Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+        }
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
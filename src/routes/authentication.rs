@@ -1,9 +1,17 @@
 //! Contains all routes and types that are being used for authentication
 
+use crate::audit::{AuditAction, AuditRecord};
+use crate::auth::oidc::OidcClient;
+use crate::auth::{AuthError, Authenticator, DATABASE_PROVIDER};
+use crate::csrf::{CsrfForm, EmptyForm};
+use crate::database::Password;
+use crate::events::{EventSender, Outcome, SecurityEvent};
+use crate::permissions::Permissions;
 use crate::routes::{FlashContext, VaultResponse};
-use crate::sessions::{SafeSessionManager, SESSION_TOKEN_COOKIE};
+use crate::sessions::{SafeSessionManager, TokenAuthResult, WithCookie};
 use crate::{templates, VaultConfig, VaultDb};
 use rocket::{form, http, request};
+use std::net::IpAddr;
 
 pub fn get_routes() -> Vec<rocket::Route> {
     rocket::routes![
@@ -15,9 +23,20 @@ pub fn get_routes() -> Vec<rocket::Route> {
     ]
 }
 
+#[derive(serde::Serialize)]
+struct LoginContext {
+    flash: FlashContext,
+    /// Whether the login form asks for a username
+    username: bool,
+    /// The name of the single sign-on provider, if one is configured
+    oidc: Option<String>,
+}
+
 #[rocket::get("/login")]
 async fn login(
     config: &rocket::State<VaultConfig>,
+    authenticator: &rocket::State<Authenticator>,
+    oidc: Option<&rocket::State<OidcClient>>,
     database: &rocket::State<VaultDb>,
     flash: Option<request::FlashMessage<'_>>,
 ) -> VaultResponse<templates::Template> {
@@ -26,9 +45,13 @@ async fn login(
             if passwords.is_empty() {
                 VaultResponse::redirect_to(rocket::uri!(new_admin_password))
             } else {
-                let context = FlashContext::default()
-                    .with_config(config)
-                    .with_optional_flash(flash);
+                let context = LoginContext {
+                    flash: FlashContext::default()
+                        .with_config(config)
+                        .with_optional_flash(flash),
+                    username: authenticator.requires_username(),
+                    oidc: oidc.map(|o| o.display_name().to_string()),
+                };
                 VaultResponse::Ok(templates::Template::render("login", context))
             }
         }
@@ -38,48 +61,157 @@ async fn login(
 
 #[derive(rocket::FromForm)]
 struct LoginFormData<'a> {
+    /// Only required for accounts of a directory service
+    username: Option<&'a str>,
     password: &'a str,
 }
 
 #[rocket::post("/login", data = "<form>")]
+#[allow(clippy::too_many_arguments)]
 async fn login_submit(
     form: form::Form<LoginFormData<'_>>,
+    client_ip: Option<IpAddr>,
     cookies: &http::CookieJar<'_>,
     config: &rocket::State<VaultConfig>,
+    authenticator: &rocket::State<Authenticator>,
     session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
 ) -> VaultResponse<()> {
-    match database.fetch_password(form.password).await {
-        Ok(password) => {
-            if let Some(p) = password {
-                let token = session_manager.lock().await.generate_session(
-                    config.token_length as usize,
-                    std::time::Duration::from_secs(config.token_validity_duration_secs),
-                    p.admin,
-                );
-                cookies.add(
-                    http::Cookie::build(SESSION_TOKEN_COOKIE, token.0)
-                        .max_age(time::Duration::seconds(
-                            config.token_validity_duration_secs as i64,
-                        ))
-                        .http_only(true)
-                        .finish(),
-                );
-                VaultResponse::redirect_to(rocket::uri!(super::vault::vault))
-            } else {
-                VaultResponse::flash_error_redirect_to(
-                    rocket::uri!(login),
-                    "The given password is wrong, please try again",
-                )
-            }
+    let username = form.username.filter(|u| !u.is_empty());
+    match authenticator
+        .authenticate(database, username, form.password)
+        .await
+    {
+        Ok(Some(account)) => {
+            log_in(
+                account,
+                client_ip,
+                None,
+                cookies,
+                config,
+                session_manager,
+                database,
+                events,
+            )
+            .await
         }
+        Ok(None) => {
+            events.emit(
+                SecurityEvent::new(AuditAction::Login.name(), Outcome::Failure)
+                    .with_client_ip(client_ip)
+                    .with_details("wrong password"),
+            );
+            VaultResponse::flash_error_redirect_to(
+                rocket::uri!(login),
+                "The given password is wrong, please try again",
+            )
+        }
+        Err(AuthError::NameTaken(_)) => VaultResponse::flash_error_redirect_to(
+            rocket::uri!(login),
+            "A local account with the same name already exists, please contact an admin",
+        ),
         Err(_) => VaultResponse::Err(http::Status::InternalServerError),
     }
 }
 
-#[rocket::post("/logout")]
-async fn logout_submit(cookies: &http::CookieJar<'_>) -> VaultResponse<()> {
-    cookies.remove(http::Cookie::named(SESSION_TOKEN_COOKIE));
+/// Starts a new session for the given account, whose credentials were already checked
+///
+/// Leads the account to `next` afterwards, if it doesn't have to change its password first.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn log_in(
+    account: Password,
+    client_ip: Option<IpAddr>,
+    next: Option<&str>,
+    cookies: &http::CookieJar<'_>,
+    config: &VaultConfig,
+    session_manager: &SafeSessionManager,
+    database: &VaultDb,
+    events: &EventSender,
+) -> VaultResponse<()> {
+    if account.disabled {
+        events.emit(
+            SecurityEvent::new(AuditAction::Login.name(), Outcome::Failure)
+                .with_user(account.id)
+                .with_client_ip(client_ip)
+                .with_details("account disabled"),
+        );
+        return VaultResponse::flash_error_redirect_to(
+            rocket::uri!(login),
+            "This account is disabled",
+        );
+    }
+    //Sessions that need a password reset can only be used to change the password
+    let permissions = if account.password_reset {
+        Permissions::none()
+    } else {
+        match database
+            .fetch_permissions(&account, &config.default_role)
+            .await
+        {
+            Ok(permissions) => permissions,
+            Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+        }
+    };
+    let token = session_manager.lock().await.generate_session(
+        config.token_length as usize,
+        std::time::Duration::from_secs(config.token_validity_duration_secs),
+        account.id,
+        permissions,
+    );
+    cookies.add(
+        config
+            .session_cookie
+            .build(token.0, config.token_validity_duration_secs),
+    );
+    let mut details = vec![];
+    if let Some(ip) = client_ip {
+        details.push(format!("from {}", ip));
+    }
+    if account.provider != DATABASE_PROVIDER {
+        details.push(format!("via {}", account.provider));
+    }
+    let mut record = AuditRecord::new(AuditAction::Login);
+    if !details.is_empty() {
+        record = record.with_details(details.join(" "));
+    }
+    super::audit_user(database, events, account.id, record).await;
+    if account.password_reset {
+        VaultResponse::flash_error_redirect_to(
+            rocket::uri!(super::account::change_password),
+            "Your password has to be changed before you can continue",
+        )
+    } else {
+        match next {
+            Some(next) => super::redirect_to_local(next),
+            None => VaultResponse::redirect_to(rocket::uri!(super::vault::vault)),
+        }
+    }
+}
+
+#[rocket::post("/logout", data = "<_form>")]
+async fn logout_submit(
+    auth: TokenAuthResult<WithCookie>,
+    cookies: &http::CookieJar<'_>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    _form: CsrfForm<EmptyForm>,
+) -> VaultResponse<()> {
+    if let Ok(token) = auth {
+        super::audit(
+            database,
+            events,
+            session_manager,
+            token.token(),
+            AuditRecord::new(AuditAction::Logout),
+        )
+        .await;
+        session_manager.lock().await.revoke_session(token.token());
+    }
+    //The removal cookie needs the same attributes, browsers ignore an insecure cookie with the `__Host-` prefix
+    cookies.add(config.session_cookie.build(String::new(), 0));
     VaultResponse::flash_success_redirect_to(rocket::uri!(login), "Logged out")
 }
 
@@ -106,7 +238,6 @@ async fn new_admin_password(
 
 #[derive(rocket::FromForm)]
 struct NewAdminPasswordData<'a> {
-    #[field(validate = len(8..))]
     password: &'a str,
     #[field(name = "password_confirm", validate = eq(self.password))]
     _password_confirm: &'a str,
@@ -115,13 +246,20 @@ struct NewAdminPasswordData<'a> {
 #[rocket::post("/new-admin-password", data = "<form>")]
 async fn new_admin_password_form(
     form: form::Form<form::Contextual<'_, NewAdminPasswordData<'_>>>,
+    config: &rocket::State<VaultConfig>,
     database: &rocket::State<VaultDb>,
 ) -> VaultResponse<String> {
     match database.fetch_all_password(true).await {
         Ok(passwords) => {
             if passwords.is_empty() {
                 if let Some(ref data) = form.value {
-                    if database
+                    if let Err(violation) = config.password_policy.check(data.password, &["Admin"])
+                    {
+                        VaultResponse::flash_error_redirect_to(
+                            rocket::uri!(new_admin_password),
+                            violation.to_string(),
+                        )
+                    } else if database
                         .insert_password("Admin", data.password, true)
                         .await
                         .is_ok()
@@ -0,0 +1,440 @@
+//! Contains all routes and types of the group management page
+
+use crate::audit::{AuditAction, AuditRecord};
+use crate::csrf::CsrfForm;
+use crate::database::{Group, GroupMember, GroupRole, Password, Role};
+use crate::events::EventSender;
+use crate::routes::{FlashContext, VaultResponse};
+use crate::sessions::{SafeSessionManager, TokenAuth, TokenAuthResult, WithCookie};
+use crate::{templates, VaultConfig, VaultDb};
+use rocket::{http, request};
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        groups,
+        add_group_submit,
+        rename_group_submit,
+        delete_group_submit,
+        add_member_submit,
+        remove_member_submit,
+        assign_role_submit,
+        unassign_role_submit
+    ]
+}
+
+#[derive(serde::Serialize)]
+struct Reference {
+    id: u64,
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+struct GroupContext {
+    id: u64,
+    name: String,
+    members: Vec<Reference>,
+    roles: Vec<Reference>,
+}
+
+impl GroupContext {
+    fn new(
+        group: Group,
+        passwords: &[Password],
+        members: &[GroupMember],
+        roles: &[Role],
+        group_roles: &[GroupRole],
+    ) -> Self {
+        Self {
+            id: group.id,
+            name: group.name,
+            members: passwords
+                .iter()
+                .filter(|p| {
+                    members
+                        .iter()
+                        .any(|m| m.group_id == group.id && m.auth_id == p.id)
+                })
+                .map(|p| Reference {
+                    id: p.id,
+                    name: p.name.clone(),
+                })
+                .collect(),
+            roles: roles
+                .iter()
+                .filter(|r| {
+                    group_roles
+                        .iter()
+                        .any(|gr| gr.group_id == group.id && gr.role_id == r.id)
+                })
+                .map(|r| Reference {
+                    id: r.id,
+                    name: r.name.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default, serde::Serialize)]
+struct GroupsContext {
+    flash: FlashContext,
+    groups: Vec<GroupContext>,
+    passwords: Vec<Password>,
+    roles: Vec<Role>,
+}
+
+#[rocket::get("/admin/groups")]
+async fn groups(
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        if session_manager
+            .lock()
+            .await
+            .is_admin_session(token.token())
+            .unwrap_or(false)
+        {
+            match (
+                database.fetch_all_groups().await,
+                database.fetch_all_password(false).await,
+                database.fetch_all_group_members().await,
+                database.fetch_all_roles().await,
+                database.fetch_all_group_roles().await,
+            ) {
+                (Ok(groups), Ok(passwords), Ok(members), Ok(roles), Ok(group_roles)) => {
+                    let groups = groups
+                        .into_iter()
+                        .map(|g| GroupContext::new(g, &passwords, &members, &roles, &group_roles))
+                        .collect();
+                    VaultResponse::Ok(templates::Template::render(
+                        "groups",
+                        GroupsContext {
+                            flash: FlashContext::default()
+                                .with_config(config)
+                                .with_csrf_token(token.csrf_token())
+                                .with_optional_flash(flash),
+                            groups,
+                            passwords,
+                            roles,
+                        },
+                    ))
+                }
+                _ => VaultResponse::Err(http::Status::InternalServerError),
+            }
+        } else {
+            VaultResponse::Err(http::Status::Unauthorized)
+        }
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct AddGroupData {
+    #[field(validate = len(1..=64))]
+    name: String,
+}
+
+#[rocket::post("/admin/groups/add", data = "<form>")]
+async fn add_group_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<AddGroupData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database.insert_group(&form.name).await {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::CreateGroup)
+                    .with_details(format!("group {}", form.name)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(rocket::uri!(groups), "Created group")
+        }
+        Err(sqlx::Error::Database(e)) => {
+            VaultResponse::flash_error_redirect_to(rocket::uri!(groups), e.message())
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct RenameGroupData {
+    group_id: u64,
+    #[field(validate = len(1..=64))]
+    name: String,
+}
+
+#[rocket::post("/admin/groups/rename", data = "<form>")]
+async fn rename_group_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<RenameGroupData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database.update_group_name(form.group_id, &form.name).await {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::RenameGroup)
+                    .with_details(format!("group {} to {}", form.group_id, form.name)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(rocket::uri!(groups), "Renamed group")
+        }
+        Err(sqlx::Error::Database(e)) => {
+            VaultResponse::flash_error_redirect_to(rocket::uri!(groups), e.message())
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct DeleteGroupData {
+    group_id: u64,
+}
+
+#[rocket::post("/admin/groups/delete", data = "<form>")]
+async fn delete_group_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<DeleteGroupData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    //The memberships are deleted along with the group
+    let members = match database.fetch_group_member_ids(form.group_id).await {
+        Ok(members) => members,
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    match database.delete_group(form.group_id).await {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &members, auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::DeleteGroup)
+                    .with_details(format!("group {}", form.group_id)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(groups),
+                "Deleted group, its members were logged out",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct MemberData {
+    group_id: u64,
+    password_id: u64,
+}
+
+#[rocket::post("/admin/groups/add-member", data = "<form>")]
+async fn add_member_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<MemberData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database
+        .insert_group_member(form.group_id, form.password_id)
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &[form.password_id], auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::AddGroupMember).with_details(format!(
+                    "account {} to group {}",
+                    form.password_id, form.group_id
+                )),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(groups))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[rocket::post("/admin/groups/remove-member", data = "<form>")]
+async fn remove_member_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<MemberData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database
+        .delete_group_member(form.group_id, form.password_id)
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &[form.password_id], auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::RemoveGroupMember).with_details(format!(
+                    "account {} from group {}",
+                    form.password_id, form.group_id
+                )),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(groups))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct GroupRoleData {
+    group_id: u64,
+    role_id: u64,
+}
+
+#[rocket::post("/admin/groups/assign", data = "<form>")]
+async fn assign_role_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<GroupRoleData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    let members = match database.fetch_group_member_ids(form.group_id).await {
+        Ok(members) => members,
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    match database
+        .insert_group_role(form.group_id, form.role_id)
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &members, auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::AssignRole)
+                    .with_details(format!("role {} to group {}", form.role_id, form.group_id)),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(groups))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[rocket::post("/admin/groups/unassign", data = "<form>")]
+async fn unassign_role_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<GroupRoleData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    let members = match database.fetch_group_member_ids(form.group_id).await {
+        Ok(members) => members,
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    match database
+        .delete_group_role(form.group_id, form.role_id)
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &members, auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::UnassignRole).with_details(format!(
+                    "role {} from group {}",
+                    form.role_id, form.group_id
+                )),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(groups))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
@@ -0,0 +1,273 @@
+//! Contains the read-only compatibility API of the HashiCorp Vault KV secrets engine (version 2)
+//!
+//! Tools that already read secrets from HashiCorp Vault can read rows of the vault instead. The table and the key of a
+//! row form the path of the secret, `GET /v1/<mount>/data/<table>/<key>` returns the decrypted cells of the row. The
+//! token is sent in the `X-Vault-Token` header and can be a session token or a personal access token.
+
+use crate::audit::{AuditAction, AuditRecord};
+use crate::database::TableRow;
+use crate::events::EventSender;
+use crate::permissions::Permission;
+use crate::sessions::{SafeSessionManager, SessionMetadata, TokenAuthResult, WithVaultToken};
+use crate::{crypt, VaultConfig, VaultDb};
+use rocket::http::uri::{fmt::Path, Segments};
+use rocket::serde::json::{serde_json, Json};
+use rocket::{http, request, response};
+use std::collections;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct KvConfig {
+    /// The path of the secrets engine, which is mounted at `/v1/<mount>`
+    #[serde(default = "default_mount")]
+    pub mount: String,
+}
+
+fn default_mount() -> String {
+    "secret".to_string()
+}
+
+/// The routes of the secrets engine, which are mounted at `/v1/<mount>`
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![secret]
+}
+
+/// The routes that clients use to find out the version of the secrets engine, which are mounted at `/v1/sys`
+pub fn get_sys_routes() -> Vec<rocket::Route> {
+    rocket::routes![mount_info]
+}
+
+pub fn get_catchers() -> Vec<rocket::Catcher> {
+    rocket::catchers![default_catcher]
+}
+
+/// An error response of HashiCorp Vault, serialized as `{"errors": ["..."]}`
+#[derive(Debug)]
+pub struct KvError(http::Status, Vec<String>);
+
+impl KvError {
+    fn new<M: Into<String>>(status: http::Status, message: M) -> Self {
+        Self(status, vec![message.into()])
+    }
+
+    /// A missing secret is answered without any message, just like HashiCorp Vault does
+    fn not_found() -> Self {
+        Self(http::Status::NotFound, vec![])
+    }
+
+    fn permission_denied() -> Self {
+        Self::new(http::Status::Forbidden, "permission denied")
+    }
+}
+
+impl From<sqlx::Error> for KvError {
+    fn from(_: sqlx::Error) -> Self {
+        //The error itself is already logged by the database
+        Self::new(http::Status::InternalServerError, "internal error")
+    }
+}
+
+impl<'r> response::Responder<'r, 'static> for KvError {
+    fn respond_to(self, request: &'r request::Request<'_>) -> response::Result<'static> {
+        response::Response::build_from(
+            Json(serde_json::json!({ "errors": self.1 })).respond_to(request)?,
+        )
+        .status(self.0)
+        .ok()
+    }
+}
+
+#[rocket::catch(default)]
+fn default_catcher(status: http::Status, _request: &request::Request) -> KvError {
+    match status.code {
+        404 => KvError::not_found(),
+        _ => KvError::new(status, status.reason().unwrap_or("Unknown")),
+    }
+}
+
+/// The response of a read, only `data` contains information of the vault
+#[derive(serde::Serialize)]
+struct KvResponse<T> {
+    request_id: String,
+    lease_id: String,
+    renewable: bool,
+    lease_duration: u64,
+    data: T,
+    wrap_info: Option<()>,
+    warnings: Option<()>,
+    auth: Option<()>,
+}
+
+impl<T> KvResponse<T> {
+    fn new(data: T) -> Self {
+        Self {
+            request_id: String::new(),
+            lease_id: String::new(),
+            renewable: false,
+            lease_duration: 0,
+            data,
+            wrap_info: None,
+            warnings: None,
+            auth: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SecretData {
+    /// The cells of the row, mapped from the column name to the plain value
+    data: collections::BTreeMap<String, String>,
+    metadata: SecretMetadata,
+}
+
+/// Rows have no history, so every secret is in its first version
+#[derive(serde::Serialize)]
+struct SecretMetadata {
+    created_time: String,
+    custom_metadata: Option<()>,
+    deletion_time: String,
+    destroyed: bool,
+    version: u32,
+}
+
+impl From<TableRow> for SecretData {
+    fn from(row: TableRow) -> Self {
+        Self {
+            data: row
+                .cells
+                .into_iter()
+                .filter(|c| c.column != "key_")
+                .map(|c| (c.column, c.data))
+                .collect(),
+            metadata: SecretMetadata {
+                created_time: "1970-01-01T00:00:00Z".to_string(),
+                custom_metadata: None,
+                deletion_time: String::new(),
+                destroyed: false,
+                version: 1,
+            },
+        }
+    }
+}
+
+/// Returns the session of the token, HashiCorp Vault denies all requests without a valid token the same way
+async fn fetch_session(
+    session_manager: &SafeSessionManager,
+    auth: TokenAuthResult<WithVaultToken>,
+) -> Result<SessionMetadata, KvError> {
+    let auth = auth.map_err(|_| KvError::permission_denied())?;
+    session_manager
+        .lock()
+        .await
+        .get_session_metadata(auth.token())
+        .ok_or_else(KvError::permission_denied)
+}
+
+/// Resolves a readable table by its name or its id, tables without read access are treated as if they would not exist
+async fn find_table_id(
+    database: &VaultDb,
+    session: &SessionMetadata,
+    table: &str,
+) -> Result<u64, KvError> {
+    let index = super::fetch_accessible_table_index(database, session).await?;
+    let id = index
+        .iter()
+        .find(|e| e.ui_name == table)
+        .or_else(|| index.iter().find(|e| e.id.to_string() == table))
+        .map(|e| e.id)
+        .ok_or_else(KvError::not_found)?;
+    let permissions = super::fetch_table_permissions(database, session, id).await?;
+    if permissions.contains(Permission::Read) {
+        Ok(id)
+    } else {
+        Err(KvError::not_found())
+    }
+}
+
+/// Returns the decrypted cells of the row with the given key
+///
+/// Just like the JSON API, decrypting requires a recent confirmation and is recorded in the audit log.
+#[rocket::get("/data/<table>/<key..>")]
+#[allow(clippy::too_many_arguments)]
+async fn secret(
+    table: String,
+    key: Segments<'_, Path>,
+    auth: TokenAuthResult<WithVaultToken>,
+    config: &rocket::State<VaultConfig>,
+    keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> Result<Json<KvResponse<SecretData>>, KvError> {
+    let session = fetch_session(session_manager, auth).await?;
+    //Keys may contain slashes, just like the paths of secrets
+    let key = key.collect::<Vec<_>>().join("/");
+    let table_id = find_table_id(database, &session, &table).await?;
+    let mut vault_table = database
+        .fetch_table(table_id, &Some(key.clone()))
+        .await?
+        .ok_or_else(KvError::not_found)?;
+    vault_table
+        .rows
+        .retain(|r| r.cells.iter().any(|c| c.column == "key_" && c.data == key));
+    if vault_table.rows.len() > 1 {
+        return Err(KvError::new(
+            http::Status::Conflict,
+            format!("there are several rows with the key {}", key),
+        ));
+    }
+    let row_id = vault_table.rows.first().ok_or_else(KvError::not_found)?.id;
+    if !session.is_confirmed_within(std::time::Duration::from_secs(config.sudo_duration_secs)) {
+        return Err(match session.access_token {
+            Some(_) => KvError::new(
+                http::Status::Forbidden,
+                "decrypting isn't in the scope of this personal access token",
+            ),
+            None => KvError::new(
+                http::Status::Forbidden,
+                "decrypting requires a recent confirmation, please confirm the session with POST /api/v1/session/confirm",
+            ),
+        });
+    }
+    //Nothing is decrypted if the reveal can't be recorded
+    super::audit_before(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::Reveal)
+            .with_table(table_id)
+            .with_row(row_id)
+            .with_details("row via kv api"),
+    )
+    .await?;
+    vault_table.decrypt(keypair);
+    let row = vault_table.rows.pop().ok_or_else(KvError::not_found)?;
+    Ok(Json(KvResponse::new(SecretData::from(row))))
+}
+
+#[derive(serde::Serialize)]
+struct MountInfo {
+    path: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    options: collections::BTreeMap<&'static str, &'static str>,
+}
+
+/// Tells clients like `vault kv get` that the secrets engine is version 2, otherwise they would use version 1 paths
+#[rocket::get("/internal/ui/mounts/<path..>")]
+async fn mount_info(
+    path: Segments<'_, Path>,
+    auth: TokenAuthResult<WithVaultToken>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+) -> Result<Json<KvResponse<MountInfo>>, KvError> {
+    fetch_session(session_manager, auth).await?;
+    let mount = match &config.kv {
+        Some(kv) if path.get(0) == Some(kv.mount.as_str()) => kv.mount.clone(),
+        _ => return Err(KvError::permission_denied()),
+    };
+    Ok(Json(KvResponse::new(MountInfo {
+        path: format!("{}/", mount),
+        kind: "kv",
+        options: collections::BTreeMap::from([("version", "2")]),
+    })))
+}
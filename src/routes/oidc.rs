@@ -0,0 +1,178 @@
+//! Contains all routes of the single sign-on with an OpenID Connect provider
+
+use crate::audit::{AuditAction, AuditRecord};
+use crate::auth::oidc::OidcClient;
+use crate::auth::AuthError;
+use crate::events::{EventSender, Outcome, SecurityEvent};
+use crate::routes::{FlashContext, VaultResponse};
+use crate::sessions::{SafeSessionManager, TokenAuthResult, WithCookie};
+use crate::{templates, VaultConfig, VaultDb};
+use rocket::http;
+use std::net::IpAddr;
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![oidc_login, oidc_callback, oidc_finish]
+}
+
+/// The cookie that binds a login to the browser that started it
+const STATE_COOKIE: &str = "oidc_state";
+
+#[rocket::get("/oidc/login?<next>")]
+async fn oidc_login(
+    next: Option<String>,
+    auth: TokenAuthResult<WithCookie>,
+    cookies: &http::CookieJar<'_>,
+    config: &rocket::State<VaultConfig>,
+    oidc: Option<&rocket::State<OidcClient>>,
+) -> VaultResponse<()> {
+    let oidc = match oidc {
+        Some(oidc) => oidc,
+        None => return VaultResponse::Err(http::Status::NotFound),
+    };
+    //A logged in account has to authenticate again to confirm an action
+    match oidc.begin_login(next, auth.is_ok()).await {
+        Ok(request) => {
+            cookies.add(
+                http::Cookie::build(STATE_COOKIE, request.state)
+                    .path("/oidc")
+                    .http_only(true)
+                    .secure(config.session_cookie.secure || config.session_cookie.host_prefix)
+                    .same_site(http::SameSite::Lax)
+                    .finish(),
+            );
+            VaultResponse::redirect_to(request.url)
+        }
+        Err(e) => {
+            rocket::warn!(
+                "Failed to start the login at the OpenID Connect provider: {}",
+                e
+            );
+            VaultResponse::flash_error_redirect_to(
+                rocket::uri!(super::authentication::login),
+                "The single sign-on is currently not available",
+            )
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct CallbackContext {
+    flash: FlashContext,
+    url: String,
+}
+
+/// The provider redirects here after the login
+///
+/// The browser doesn't send the session cookie along with a redirect from another site, so the login is completed
+/// by [`oidc_finish`], which the page continues to.
+#[rocket::get("/oidc/callback?<code>&<state>&<error>")]
+async fn oidc_callback(
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    config: &rocket::State<VaultConfig>,
+) -> templates::Template {
+    let context = CallbackContext {
+        flash: FlashContext::default().with_config(config),
+        url: rocket::uri!(oidc_finish(
+            code.as_deref(),
+            state.as_deref(),
+            error.as_deref()
+        ))
+        .to_string(),
+    };
+    templates::Template::render("oidc-callback", context)
+}
+
+#[rocket::get("/oidc/finish?<code>&<state>&<error>")]
+#[allow(clippy::too_many_arguments)]
+async fn oidc_finish(
+    code: Option<&str>,
+    state: Option<&str>,
+    error: Option<&str>,
+    auth: TokenAuthResult<WithCookie>,
+    client_ip: Option<IpAddr>,
+    cookies: &http::CookieJar<'_>,
+    config: &rocket::State<VaultConfig>,
+    oidc: Option<&rocket::State<OidcClient>>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    let oidc = match oidc {
+        Some(oidc) => oidc,
+        None => return VaultResponse::Err(http::Status::NotFound),
+    };
+    let expected_state = cookies.get(STATE_COOKIE).map(|c| c.value().to_string());
+    cookies.remove(http::Cookie::build(STATE_COOKIE, "").path("/oidc").finish());
+    let (code, state) = match (code, state, error) {
+        (Some(code), Some(state), None) if expected_state.as_deref() == Some(state) => {
+            (code, state)
+        }
+        (_, _, error) => {
+            events.emit(
+                SecurityEvent::new(AuditAction::Login.name(), Outcome::Failure)
+                    .with_client_ip(client_ip)
+                    .with_details(format!("oidc: {}", error.unwrap_or("invalid state"))),
+            );
+            return VaultResponse::flash_error_redirect_to(
+                rocket::uri!(super::authentication::login),
+                "The single sign-on was cancelled, please try again",
+            );
+        }
+    };
+
+    match oidc.finish_login(database, state, code).await {
+        Ok((account, next, reauthenticated)) => {
+            //An account that is still logged in only confirmed its credentials
+            if let (Ok(token), true) = (&auth, reauthenticated) {
+                let user_id = session_manager
+                    .lock()
+                    .await
+                    .get_session_metadata(token.token())
+                    .map(|s| s.user_id);
+                if user_id == Some(account.id) {
+                    session_manager.lock().await.confirm_session(token.token());
+                    super::audit_user(
+                        database,
+                        events,
+                        account.id,
+                        AuditRecord::new(AuditAction::ConfirmPassword),
+                    )
+                    .await;
+                    return match next {
+                        Some(next) => super::redirect_to_local(&next),
+                        None => VaultResponse::redirect_to(rocket::uri!(super::vault::vault)),
+                    };
+                }
+            }
+            super::authentication::log_in(
+                account,
+                client_ip,
+                next.as_deref(),
+                cookies,
+                config,
+                session_manager,
+                database,
+                events,
+            )
+            .await
+        }
+        Err(AuthError::NameTaken(_)) => VaultResponse::flash_error_redirect_to(
+            rocket::uri!(super::authentication::login),
+            "A local account with the same name already exists, please contact an admin",
+        ),
+        Err(e) => {
+            rocket::warn!("The login at the OpenID Connect provider failed: {}", e);
+            events.emit(
+                SecurityEvent::new(AuditAction::Login.name(), Outcome::Failure)
+                    .with_client_ip(client_ip)
+                    .with_details(format!("oidc: {}", e)),
+            );
+            VaultResponse::flash_error_redirect_to(
+                rocket::uri!(super::authentication::login),
+                "The single sign-on failed, please try again",
+            )
+        }
+    }
+}
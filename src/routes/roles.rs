@@ -0,0 +1,369 @@
+//! Contains all routes and types of the role management page
+
+use crate::audit::{AuditAction, AuditRecord};
+use crate::csrf::CsrfForm;
+use crate::database::{Password, Role, RoleAssignment};
+use crate::events::EventSender;
+use crate::permissions::{Permission, Permissions};
+use crate::routes::{FlashContext, VaultResponse};
+use crate::sessions::{SafeSessionManager, TokenAuth, TokenAuthResult, WithCookie};
+use crate::{templates, VaultConfig, VaultDb};
+use rocket::{http, request};
+
+pub fn get_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        roles,
+        add_role_submit,
+        update_role_submit,
+        delete_role_submit,
+        assign_role_submit,
+        unassign_role_submit
+    ]
+}
+
+#[derive(serde::Serialize)]
+struct AccountContext {
+    id: u64,
+    name: String,
+    admin: bool,
+    roles: Vec<RoleReference>,
+}
+
+#[derive(serde::Serialize)]
+struct RoleReference {
+    id: u64,
+    name: String,
+}
+
+impl AccountContext {
+    fn new(password: Password, roles: &[Role], assignments: &[RoleAssignment]) -> Self {
+        Self {
+            id: password.id,
+            name: password.name,
+            admin: password.admin,
+            roles: roles
+                .iter()
+                .filter(|r| {
+                    assignments
+                        .iter()
+                        .any(|a| a.auth_id == password.id && a.role_id == r.id)
+                })
+                .map(|r| RoleReference {
+                    id: r.id,
+                    name: r.name.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Default, serde::Serialize)]
+struct RolesContext {
+    flash: FlashContext,
+    roles: Vec<Role>,
+    accounts: Vec<AccountContext>,
+    default_role: String,
+}
+
+#[rocket::get("/admin/roles")]
+async fn roles(
+    auth: TokenAuthResult<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    flash: Option<request::FlashMessage<'_>>,
+) -> VaultResponse<templates::Template> {
+    if let Ok(token) = auth {
+        if session_manager
+            .lock()
+            .await
+            .is_admin_session(token.token())
+            .unwrap_or(false)
+        {
+            match (
+                database.fetch_all_roles().await,
+                database.fetch_all_password(false).await,
+                database.fetch_all_role_assignments().await,
+            ) {
+                (Ok(roles), Ok(passwords), Ok(assignments)) => {
+                    let accounts = passwords
+                        .into_iter()
+                        .map(|p| AccountContext::new(p, &roles, &assignments))
+                        .collect();
+                    VaultResponse::Ok(templates::Template::render(
+                        "roles",
+                        RolesContext {
+                            flash: FlashContext::default()
+                                .with_config(config)
+                                .with_csrf_token(token.csrf_token())
+                                .with_optional_flash(flash),
+                            roles,
+                            accounts,
+                            default_role: config.default_role.clone(),
+                        },
+                    ))
+                }
+                _ => VaultResponse::Err(http::Status::InternalServerError),
+            }
+        } else {
+            VaultResponse::Err(http::Status::Unauthorized)
+        }
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct AddRoleData {
+    #[field(validate = len(1..=64))]
+    name: String,
+    permissions: Vec<Permission>,
+}
+
+#[rocket::post("/admin/roles/add", data = "<form>")]
+async fn add_role_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<AddRoleData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database
+        .insert_role(
+            &form.name,
+            form.permissions.iter().copied().collect::<Permissions>(),
+        )
+        .await
+    {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::CreateRole).with_details(format!(
+                    "role {}: {}",
+                    form.name,
+                    form.permissions
+                        .iter()
+                        .map(Permission::name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(rocket::uri!(roles), "Created role")
+        }
+        Err(sqlx::Error::Database(e)) => {
+            VaultResponse::flash_error_redirect_to(rocket::uri!(roles), e.message())
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct UpdateRoleData {
+    role_id: u64,
+    permissions: Vec<Permission>,
+}
+
+#[rocket::post("/admin/roles/update", data = "<form>")]
+async fn update_role_submit(
+    auth: TokenAuth<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<UpdateRoleData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    let affected = match database
+        .fetch_role_account_ids(form.role_id, &config.default_role)
+        .await
+    {
+        Ok(affected) => affected,
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    match database
+        .update_role_permissions(
+            form.role_id,
+            form.permissions.iter().copied().collect::<Permissions>(),
+        )
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &affected, auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::UpdateRole).with_details(format!(
+                    "role {}: {}",
+                    form.role_id,
+                    form.permissions
+                        .iter()
+                        .map(Permission::name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(roles),
+                "Updated role, the affected accounts were logged out",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct DeleteRoleData {
+    role_id: u64,
+}
+
+#[rocket::post("/admin/roles/delete", data = "<form>")]
+async fn delete_role_submit(
+    auth: TokenAuth<WithCookie>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<DeleteRoleData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    //The assignments are deleted along with the role
+    let affected = match database
+        .fetch_role_account_ids(form.role_id, &config.default_role)
+        .await
+    {
+        Ok(affected) => affected,
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    match database.delete_role(form.role_id).await {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &affected, auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::DeleteRole)
+                    .with_details(format!("role {}", form.role_id)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(roles),
+                "Deleted role, the affected accounts were logged out",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct RoleAssignmentData {
+    password_id: u64,
+    role_id: u64,
+}
+
+#[rocket::post("/admin/roles/assign", data = "<form>")]
+async fn assign_role_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<RoleAssignmentData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database
+        .insert_role_assignment(form.password_id, form.role_id)
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &[form.password_id], auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::AssignRole).with_details(format!(
+                    "role {} to account {}",
+                    form.role_id, form.password_id
+                )),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(roles))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[rocket::post("/admin/roles/unassign", data = "<form>")]
+async fn unassign_role_submit(
+    auth: TokenAuth<WithCookie>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+    form: CsrfForm<RoleAssignmentData>,
+) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .is_admin_session(auth.token())
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
+    match database
+        .delete_role_assignment(form.password_id, form.role_id)
+        .await
+    {
+        Ok(_) => {
+            super::revoke_sessions_of(session_manager, &[form.password_id], auth.token()).await;
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::UnassignRole).with_details(format!(
+                    "role {} from account {}",
+                    form.role_id, form.password_id
+                )),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(roles))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
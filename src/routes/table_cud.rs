@@ -2,11 +2,18 @@
 
 //! Contains all routes that create, update or delete (`CUD`) tables
 
-use crate::database::VaultTable;
-use crate::routes::{FlashContext, VaultResponse};
-use crate::sessions::{TokenAuth, TokenAuthResult, WithCookie};
+use crate::audit::{AuditAction, AuditRecord};
+use crate::csrf::CsrfForm;
+use crate::database::{Group, Password, TableGrant, TableGroupGrant, VaultTable};
+use crate::events::EventSender;
+use crate::permissions::{Permission, TableAccess};
+use crate::routes::{FlashContext, GeneralContext, VaultResponse};
+use crate::sessions::{
+    SafeSessionManager, SessionMetadata, TokenAuth, TokenAuthError, TokenAuthResult,
+    WithCookieOrHeader,
+};
 use crate::{crypt, templates, VaultConfig, VaultDb};
-use rocket::{form, http, request};
+use rocket::{http, request};
 use std::collections;
 
 pub fn get_routes() -> Vec<rocket::Route> {
@@ -16,23 +23,42 @@ pub fn get_routes() -> Vec<rocket::Route> {
         add_data_submit,
         delete_data_submit,
         edit,
+        grant_submit,
+        grant_group_submit,
+        revoke_submit,
+        revoke_group_submit,
         delete_submit
     ]
 }
 
 #[rocket::get("/add")]
 async fn add(
-    auth: TokenAuthResult<WithCookie>,
+    auth: TokenAuthResult<WithCookieOrHeader>,
     config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
     flash: Option<request::FlashMessage<'_>>,
 ) -> VaultResponse<templates::Template> {
-    if auth.is_err() {
-        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    if let Ok(token) = auth {
+        if session_manager
+            .lock()
+            .await
+            .has_permission(token.token(), Permission::ManageTables)
+            .unwrap_or(false)
+        {
+            let context = FlashContext::default()
+                .with_config(config)
+                .with_csrf_token(token.csrf_token())
+                .with_optional_flash(flash);
+            VaultResponse::Ok(templates::Template::render("add-table", context))
+        } else {
+            VaultResponse::Err(http::Status::Unauthorized)
+        }
+    } else if let Err(TokenAuthError::MalformedHeader) = auth {
+        VaultResponse::Err(http::Status::BadRequest)
+    } else if let Err(TokenAuthError::AccessTokenNotAllowed) = auth {
+        VaultResponse::Err(http::Status::Forbidden)
     } else {
-        let context = FlashContext::default()
-            .with_config(config)
-            .with_optional_flash(flash);
-        VaultResponse::Ok(templates::Template::render("add-table", context))
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
     }
 }
 
@@ -48,10 +74,20 @@ struct AddTableData<'a> {
 
 #[rocket::post("/add", data = "<form>")]
 async fn add_submit(
-    _auth: TokenAuth<WithCookie>,
-    form: form::Form<AddTableData<'_>>,
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<AddTableData<'_>>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
 ) -> VaultResponse<()> {
+    if !session_manager
+        .lock()
+        .await
+        .has_permission(auth.token(), Permission::ManageTables)
+        .unwrap_or(false)
+    {
+        return VaultResponse::Err(http::Status::Unauthorized);
+    }
     let key_ui_name = if form.key_column_name.is_empty() {
         "Key"
     } else {
@@ -67,10 +103,22 @@ async fn add_submit(
         .create_vault_table(form.name, key_ui_name, password_ui_name, &form.extra)
         .await
     {
-        Ok(id) => VaultResponse::redirect_to(rocket::uri!(super::vault::vault_table_id(
-            id,
-            Option::<String>::None
-        ))),
+        Ok(id) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::CreateTable)
+                    .with_table(id)
+                    .with_details(format!("table {}", form.name)),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(super::vault::vault_table_id(
+                id,
+                Option::<String>::None
+            )))
+        }
         Err(sqlx::Error::Database(e)) => {
             VaultResponse::flash_error_redirect_to(rocket::uri!(add), e.message())
         }
@@ -86,11 +134,24 @@ struct AddDataData<'a> {
 
 #[rocket::post("/add-data", data = "<form>")]
 async fn add_data_submit(
-    _auth: TokenAuth<WithCookie>,
-    form: form::Form<AddDataData<'_>>,
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<AddDataData<'_>>,
     keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
 ) -> VaultResponse<()> {
+    if let Err(response) = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::Write,
+    )
+    .await
+    {
+        return response;
+    }
     if let Ok(index) = database.fetch_column_index_by_id(form.table_id).await {
         if index.is_empty() {
             VaultResponse::Err(http::Status::BadRequest)
@@ -108,14 +169,23 @@ async fn add_data_submit(
                     Some((entry.column_name.as_ref(), data))
                 })
                 .collect();
-            if database
+            if let Ok(result) = database
                 .insert_vault_data(
                     form.table_id,
                     data.iter().map(|d| (*d.0, d.1.as_ref())).collect(),
                 )
                 .await
-                .is_ok()
             {
+                super::audit(
+                    database,
+                    events,
+                    session_manager,
+                    auth.token(),
+                    AuditRecord::new(AuditAction::AddRow)
+                        .with_table(form.table_id)
+                        .with_row(result.last_insert_id()),
+                )
+                .await;
                 VaultResponse::redirect_to(rocket::uri!(super::vault::vault_table_id(
                     form.table_id,
                     Option::<String>::None
@@ -137,15 +207,38 @@ struct DeleteDataData {
 
 #[rocket::post("/delete-data", data = "<form>")]
 async fn delete_data_submit(
-    _auth: TokenAuth<WithCookie>,
-    form: form::Form<DeleteDataData>,
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<DeleteDataData>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
 ) -> VaultResponse<()> {
+    if let Err(response) = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::Write,
+    )
+    .await
+    {
+        return response;
+    }
     if database
         .delete_vault_row(form.table_id, form.row_id)
         .await
         .is_ok()
     {
+        super::audit(
+            database,
+            events,
+            session_manager,
+            auth.token(),
+            AuditRecord::new(AuditAction::DeleteRow)
+                .with_table(form.table_id)
+                .with_row(form.row_id),
+        )
+        .await;
         VaultResponse::redirect_to(rocket::uri!(super::vault::vault_table_id(
             form.table_id,
             Option::<String>::None
@@ -159,33 +252,328 @@ async fn delete_data_submit(
 struct EditTableContext {
     flash: FlashContext,
     table: VaultTable,
+    /// Whether only accounts and groups with a grant can access the table
+    restricted: bool,
+    grants: Vec<TableGrant>,
+    group_grants: Vec<TableGroupGrant>,
+    passwords: Vec<Password>,
+    groups: Vec<Group>,
 }
 
 #[rocket::get("/edit?<id>")]
 async fn edit(
-    auth: TokenAuthResult<WithCookie>,
+    auth: TokenAuthResult<WithCookieOrHeader>,
     id: u64,
     config: &rocket::State<VaultConfig>,
     flash: Option<request::FlashMessage<'_>>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
 ) -> VaultResponse<templates::Template> {
-    if auth.is_err() {
-        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
-    } else {
+    if let Ok(token) = auth {
+        let session = match session_manager
+            .lock()
+            .await
+            .get_session_metadata(token.token())
+        {
+            Some(session) => session,
+            None => return VaultResponse::Err(http::Status::Unauthorized),
+        };
+        let permissions = match super::fetch_table_permissions(database, &session, id).await {
+            Ok(permissions) => permissions,
+            Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+        };
+        if !permissions.contains(Permission::ManageTables)
+            && !permissions.contains(Permission::Import)
+        {
+            return VaultResponse::Err(http::Status::Unauthorized);
+        }
+        let restricted = match database.fetch_table_restricted(id).await {
+            Ok(restricted) => restricted,
+            Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+        };
+        let (grants, group_grants, passwords, groups) =
+            if permissions.contains(Permission::ManageTables) {
+                match (
+                    database.fetch_table_grants(id).await,
+                    database.fetch_table_group_grants(id).await,
+                    database.fetch_all_password(false).await,
+                    database.fetch_all_groups().await,
+                ) {
+                    (Ok(grants), Ok(group_grants), Ok(passwords), Ok(groups)) => {
+                        (grants, group_grants, passwords, groups)
+                    }
+                    _ => return VaultResponse::Err(http::Status::InternalServerError),
+                }
+            } else {
+                (vec![], vec![], vec![], vec![])
+            };
         match database.fetch_table(id, &None).await {
             Ok(table) => table.map_or(VaultResponse::Err(http::Status::NotFound), |t| {
                 VaultResponse::Ok(templates::Template::render(
                     "edit",
                     EditTableContext {
                         flash: FlashContext::default()
-                            .with_config(config.inner())
+                            .with_general_context(
+                                GeneralContext::from(config.inner())
+                                    .with_permissions(permissions)
+                                    .with_csrf_token(token.csrf_token()),
+                            )
                             .with_optional_flash(flash),
                         table: t,
+                        restricted,
+                        grants,
+                        group_grants,
+                        passwords,
+                        groups,
                     },
                 ))
             }),
             Err(_) => VaultResponse::Err(http::Status::InternalServerError),
         }
+    } else if let Err(TokenAuthError::MalformedHeader) = auth {
+        VaultResponse::Err(http::Status::BadRequest)
+    } else if let Err(TokenAuthError::AccessTokenNotAllowed) = auth {
+        VaultResponse::Err(http::Status::Forbidden)
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct GrantData {
+    table_id: u64,
+    password_id: u64,
+    access: TableAccess,
+}
+
+#[rocket::post("/edit/grant", data = "<form>")]
+async fn grant_submit(
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<GrantData>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    let session = match super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::ManageTables,
+    )
+    .await
+    {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if grant_manage_access_on_first_grant(database, &session, form.table_id)
+        .await
+        .is_err()
+    {
+        return VaultResponse::Err(http::Status::InternalServerError);
+    }
+    match database
+        .insert_table_grant(form.table_id, form.password_id, form.access)
+        .await
+    {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::GrantAccess)
+                    .with_table(form.table_id)
+                    .with_details(format!(
+                        "{:?} access for account {}",
+                        form.access, form.password_id
+                    )),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(edit(form.table_id)),
+                "Granted access",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct GroupGrantData {
+    table_id: u64,
+    group_id: u64,
+    access: TableAccess,
+}
+
+#[rocket::post("/edit/grant-group", data = "<form>")]
+async fn grant_group_submit(
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<GroupGrantData>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    let session = match super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::ManageTables,
+    )
+    .await
+    {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if grant_manage_access_on_first_grant(database, &session, form.table_id)
+        .await
+        .is_err()
+    {
+        return VaultResponse::Err(http::Status::InternalServerError);
+    }
+    match database
+        .insert_table_group_grant(form.table_id, form.group_id, form.access)
+        .await
+    {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::GrantAccess)
+                    .with_table(form.table_id)
+                    .with_details(format!(
+                        "{:?} access for group {}",
+                        form.access, form.group_id
+                    )),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(edit(form.table_id)),
+                "Granted access",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+/// Grants `manage` access to the given session's account if the given table isn't restricted yet
+///
+/// Restricting a table for the first time would otherwise lock out the account that restricted it.
+/// Admins can access all tables anyway, so they don't get a grant.
+async fn grant_manage_access_on_first_grant(
+    database: &VaultDb,
+    session: &SessionMetadata,
+    table_id: u64,
+) -> sqlx::Result<()> {
+    if session.permissions.contains(Permission::Administrate) {
+        return Ok(());
+    }
+    if !database.fetch_table_restricted(table_id).await? {
+        database
+            .insert_table_grant(table_id, session.user_id, TableAccess::Manage)
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(rocket::FromForm)]
+struct RevokeData {
+    table_id: u64,
+    password_id: u64,
+}
+
+#[rocket::post("/edit/revoke", data = "<form>")]
+async fn revoke_submit(
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<RevokeData>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    if let Err(response) = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::ManageTables,
+    )
+    .await
+    {
+        return response;
+    }
+    match database
+        .delete_table_grant(form.table_id, form.password_id)
+        .await
+    {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::RevokeAccess)
+                    .with_table(form.table_id)
+                    .with_details(format!("account {}", form.password_id)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(edit(form.table_id)),
+                "Revoked access",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
+
+#[derive(rocket::FromForm)]
+struct RevokeGroupData {
+    table_id: u64,
+    group_id: u64,
+}
+
+#[rocket::post("/edit/revoke-group", data = "<form>")]
+async fn revoke_group_submit(
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<RevokeGroupData>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<()> {
+    if let Err(response) = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::ManageTables,
+    )
+    .await
+    {
+        return response;
+    }
+    match database
+        .delete_table_group_grant(form.table_id, form.group_id)
+        .await
+    {
+        Ok(_) => {
+            super::audit(
+                database,
+                events,
+                session_manager,
+                auth.token(),
+                AuditRecord::new(AuditAction::RevokeAccess)
+                    .with_table(form.table_id)
+                    .with_details(format!("group {}", form.group_id)),
+            )
+            .await;
+            VaultResponse::flash_success_redirect_to(
+                rocket::uri!(edit(form.table_id)),
+                "Revoked access",
+            )
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
     }
 }
 
@@ -196,13 +584,41 @@ struct DeleteData {
 
 #[rocket::post("/delete", data = "<form>")]
 async fn delete_submit(
-    _auth: TokenAuth<WithCookie>,
-    form: form::Form<DeleteData>,
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<DeleteData>,
+    config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
 ) -> VaultResponse<()> {
-    database
-        .delete_vault_table(form.table_id)
-        .await
-        .map(|_| VaultResponse::redirect_to(rocket::uri!(super::vault::vault)))
-        .unwrap_or(VaultResponse::Err(http::Status::InternalServerError))
+    let session = match super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::ManageTables,
+    )
+    .await
+    {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if let Some(response) =
+        super::check_confirmed(config, &session, rocket::uri!(edit(form.table_id)))
+    {
+        return response;
+    }
+    match database.delete_vault_table(form.table_id).await {
+        Ok(_) => {
+            super::audit_user(
+                database,
+                events,
+                session.user_id,
+                AuditRecord::new(AuditAction::DeleteTable).with_table(form.table_id),
+            )
+            .await;
+            VaultResponse::redirect_to(rocket::uri!(super::vault::vault))
+        }
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
 }
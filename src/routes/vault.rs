@@ -1,18 +1,27 @@
 //! Contains all routes that can be used to read tables
 
+use crate::audit::{AuditAction, AuditRecord};
+use crate::csrf::CsrfForm;
 use crate::database::{TableIndexEntry, VaultTable};
+use crate::events::EventSender;
+use crate::permissions::Permission;
 use crate::routes::{GeneralContext, VaultResponse};
-use crate::sessions::{SafeSessionManager, TokenAuthResult, WithCookie, SESSION_TOKEN_COOKIE};
+use crate::sessions::{
+    SafeSessionManager, TokenAuth, TokenAuthError, TokenAuthResult, WithCookieOrHeader,
+};
 use crate::{crypt, templates, VaultConfig, VaultDb};
 use rocket::{http, response};
 
 pub fn get_routes() -> Vec<rocket::Route> {
-    rocket::routes![index, vault, vault_table_id]
+    rocket::routes![index, vault, vault_table_id, reveal_submit]
 }
 
 #[rocket::get("/")]
-async fn index(cookies: &http::CookieJar<'_>) -> response::Redirect {
-    if cookies.get(SESSION_TOKEN_COOKIE).is_some() {
+async fn index(
+    cookies: &http::CookieJar<'_>,
+    config: &rocket::State<VaultConfig>,
+) -> response::Redirect {
+    if cookies.get(config.session_cookie.name()).is_some() {
         response::Redirect::to(rocket::uri!(vault))
     } else {
         response::Redirect::to(rocket::uri!(super::authentication::login))
@@ -21,14 +30,21 @@ async fn index(cookies: &http::CookieJar<'_>) -> response::Redirect {
 
 #[rocket::get("/vault")]
 async fn vault(
-    auth: TokenAuthResult<WithCookie>,
+    auth: TokenAuthResult<WithCookieOrHeader>,
     config: &rocket::State<VaultConfig>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
 ) -> VaultResponse<templates::Template> {
-    if auth.is_err() {
-        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
-    } else {
-        match database.fetch_table_index().await {
+    if let Ok(token) = auth {
+        let session = match session_manager
+            .lock()
+            .await
+            .get_session_metadata(token.token())
+        {
+            Some(session) if session.permissions.contains(Permission::Read) => session,
+            _ => return VaultResponse::Err(http::Status::Unauthorized),
+        };
+        match super::fetch_accessible_table_index(database, &session).await {
             Ok(index) => {
                 if let Some(first) = index.first() {
                     VaultResponse::redirect_to(rocket::uri!(vault_table_id(
@@ -38,12 +54,20 @@ async fn vault(
                 } else {
                     VaultResponse::Ok(templates::Template::render(
                         "no-tables",
-                        GeneralContext::from(config.inner()),
+                        GeneralContext::from(config.inner())
+                            .with_permissions(session.permissions)
+                            .with_csrf_token(token.csrf_token()),
                     ))
                 }
             }
             Err(_) => VaultResponse::Err(http::Status::InternalServerError),
         }
+    } else if let Err(TokenAuthError::MalformedHeader) = auth {
+        VaultResponse::Err(http::Status::BadRequest)
+    } else if let Err(TokenAuthError::AccessTokenNotAllowed) = auth {
+        VaultResponse::Err(http::Status::Forbidden)
+    } else {
+        VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
     }
 }
 
@@ -53,6 +77,9 @@ struct TableContext {
     selected_table: VaultTable,
     tables: Vec<TableIndexEntry>,
     query: Option<String>,
+    /// Whether the session recently confirmed its password and encrypted values can be revealed
+    confirmed: bool,
+    confirm_uri: String,
 }
 
 impl TableContext {
@@ -75,54 +102,160 @@ impl TableContext {
         self.query = query;
         self
     }
+
+    fn with_confirmation(mut self, confirmed: bool, confirm_uri: String) -> Self {
+        self.confirmed = confirmed;
+        self.confirm_uri = confirm_uri;
+        self
+    }
+}
+
+/// Returns the page of the given table, optionally searched
+pub fn table_uri(table_id: u64, query: Option<String>) -> http::uri::Origin<'static> {
+    rocket::uri!(vault_table_id(table_id, query))
 }
 
 #[rocket::get("/vault?<id>&<q>")]
 async fn vault_table_id(
     id: u64,
     q: Option<String>,
-    auth: TokenAuthResult<WithCookie>,
+    auth: TokenAuthResult<WithCookieOrHeader>,
     config: &rocket::State<VaultConfig>,
-    keypair: &rocket::State<crypt::KeyPair>,
     session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
 ) -> VaultResponse<templates::Template> {
     if let Ok(token) = auth {
-        match database.fetch_table(id, &q).await {
+        let session = match session_manager
+            .lock()
+            .await
+            .get_session_metadata(token.token())
+        {
+            Some(session) if session.permissions.contains(Permission::Read) => session,
+            _ => return VaultResponse::Err(http::Status::Unauthorized),
+        };
+        let permissions = match super::fetch_table_permissions(database, &session, id).await {
+            Ok(permissions) => permissions,
+            Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+        };
+        //Tables without read access are treated as if they would not exist
+        let table = if permissions.contains(Permission::Read) {
+            database.fetch_table(id, &q).await
+        } else {
+            Ok(None)
+        };
+        let confirmed =
+            session.is_confirmed_within(std::time::Duration::from_secs(config.sudo_duration_secs));
+        let confirm_uri = rocket::uri!(super::account::confirm(
+            table_uri(id, q.clone()).to_string()
+        ))
+        .to_string();
+        match table {
             Ok(t) => {
-                let table_index = database.fetch_table_index().await; //XXXX: Can't be done in map_or closure because of `await`, better solution?
-                let admin = session_manager
-                    .lock()
-                    .await
-                    .is_admin_session(token.token())
-                    .unwrap_or(false); //XXXX: Same as above
-                VaultResponse::Ok(t.map_or(
-                    templates::Template::render(
-                        "table-not-found",
-                        GeneralContext::from(config.inner()),
-                    ),
-                    |mut table| {
-                        table.decrypt(keypair);
-                        let mut context = TableContext::default();
-                        if let Ok(mut other_tables) = table_index {
-                            other_tables.retain(|e| e.id != table.id); //Remove the selected table from the list, otherwise it would appear twice in the drop-down
-                            context = context.with_tables(other_tables);
-                        }
+                let table_index = super::fetch_accessible_table_index(database, &session).await; //XXXX: Can't be done in map_or closure because of `await`, better solution?
+                VaultResponse::Ok(
+                    t.map_or(
                         templates::Template::render(
-                            "table",
-                            context
-                                .with_general_context(
-                                    GeneralContext::from(config.inner()).with_admin(admin),
-                                )
-                                .with_optional_query(q)
-                                .with_selected_table(table),
-                        )
-                    },
-                ))
+                            "table-not-found",
+                            GeneralContext::from(config.inner())
+                                .with_permissions(session.permissions)
+                                .with_csrf_token(token.csrf_token()),
+                        ),
+                        |mut table| {
+                            //Encrypted values are only revealed one by one through `reveal_submit`
+                            table.hide_encrypted();
+                            let mut context = TableContext::default();
+                            if let Ok(mut other_tables) = table_index {
+                                other_tables.retain(|e| e.id != table.id); //Remove the selected table from the list, otherwise it would appear twice in the drop-down
+                                context = context.with_tables(other_tables);
+                            }
+                            templates::Template::render(
+                                "table",
+                                context
+                                    .with_general_context(
+                                        GeneralContext::from(config.inner())
+                                            .with_permissions(permissions)
+                                            .with_csrf_token(token.csrf_token()),
+                                    )
+                                    .with_optional_query(q)
+                                    .with_confirmation(confirmed, confirm_uri)
+                                    .with_selected_table(table),
+                            )
+                        },
+                    ),
+                )
             }
             Err(_) => VaultResponse::Err(http::Status::InternalServerError),
         }
+    } else if let Err(TokenAuthError::MalformedHeader) = auth {
+        VaultResponse::Err(http::Status::BadRequest)
+    } else if let Err(TokenAuthError::AccessTokenNotAllowed) = auth {
+        VaultResponse::Err(http::Status::Forbidden)
     } else {
         VaultResponse::redirect_to(rocket::uri!(super::authentication::login))
     }
 }
+
+#[derive(rocket::FromForm)]
+struct RevealData<'a> {
+    table_id: u64,
+    row_id: u64,
+    column: &'a str,
+}
+
+/// Decrypts a single cell, every reveal is recorded in the audit log
+#[rocket::post("/reveal", data = "<form>")]
+async fn reveal_submit(
+    auth: TokenAuth<WithCookieOrHeader>,
+    form: CsrfForm<RevealData<'_>>,
+    config: &rocket::State<VaultConfig>,
+    keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
+    database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
+) -> VaultResponse<String> {
+    let session = match super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::Read,
+    )
+    .await
+    {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if !session.is_confirmed_within(std::time::Duration::from_secs(config.sudo_duration_secs)) {
+        return VaultResponse::Err(http::Status::Forbidden);
+    }
+    let cell = match database
+        .fetch_cell(form.table_id, form.row_id, form.column)
+        .await
+    {
+        Ok(Some(cell)) => cell,
+        Ok(None) => return VaultResponse::Err(http::Status::NotFound),
+        Err(_) => return VaultResponse::Err(http::Status::InternalServerError),
+    };
+    if !cell.encrypted {
+        return VaultResponse::Ok(cell.data);
+    }
+    //Nothing is revealed if the reveal can't be recorded
+    if super::audit_before(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::Reveal)
+            .with_table(form.table_id)
+            .with_row(form.row_id)
+            .with_column(form.column),
+    )
+    .await
+    .is_err()
+    {
+        return VaultResponse::Err(http::Status::InternalServerError);
+    }
+    match keypair.decrypt_string_from_hex(&cell.data) {
+        Ok(plain) => VaultResponse::Ok(plain),
+        Err(_) => VaultResponse::Err(http::Status::InternalServerError),
+    }
+}
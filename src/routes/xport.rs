@@ -1,9 +1,13 @@
 //! Contains all routes for exporting and importing tables from CSV files
 
+use crate::audit::{AuditAction, AuditRecord};
+use crate::csrf::CsrfForm;
+use crate::events::EventSender;
+use crate::permissions::Permission;
 use crate::routes::VaultResponse;
-use crate::sessions::{TokenAuth, WithCookie};
-use crate::{crypt, VaultDb};
-use rocket::{form, fs, http};
+use crate::sessions::{SafeSessionManager, TokenAuth, WithCookieOrHeader};
+use crate::{crypt, VaultConfig, VaultDb};
+use rocket::{fs, http};
 use std::path;
 
 pub fn get_routes() -> Vec<rocket::Route> {
@@ -12,11 +16,43 @@ pub fn get_routes() -> Vec<rocket::Route> {
 
 #[rocket::get("/download/<id>")]
 async fn download(
-    _auth: TokenAuth<WithCookie>,
+    auth: TokenAuth<WithCookieOrHeader>,
     id: u64,
+    config: &rocket::State<VaultConfig>,
     keypair: &rocket::State<crypt::KeyPair>,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
 ) -> VaultResponse<(http::ContentType, String)> {
+    let session = match super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        id,
+        Permission::Export,
+    )
+    .await
+    {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    if let Some(response) =
+        super::check_confirmed(config, &session, super::vault::table_uri(id, None))
+    {
+        return response;
+    }
+    //Nothing is exported if the export can't be recorded
+    if super::audit_before(
+        database,
+        events,
+        session.user_id,
+        AuditRecord::new(AuditAction::Export).with_table(id),
+    )
+    .await
+    .is_err()
+    {
+        return VaultResponse::Err(http::Status::InternalServerError);
+    }
     match database.fetch_table(id, &None).await.map(|table| {
         table.map(|mut t| {
             t.decrypt(keypair);
@@ -37,20 +73,45 @@ struct ImportData<'a> {
 
 #[rocket::post("/import", data = "<form>")]
 async fn import_submit(
-    auth: TokenAuth<WithCookie>,
-    mut form: form::Form<ImportData<'_>>,
+    auth: TokenAuth<WithCookieOrHeader>,
+    mut form: CsrfForm<ImportData<'_>>,
     config: &rocket::Config,
+    session_manager: &rocket::State<SafeSessionManager>,
     database: &rocket::State<VaultDb>,
+    events: &rocket::State<EventSender>,
     keypair: &rocket::State<crypt::KeyPair>,
 ) -> VaultResponse<()> {
+    if let Err(response) = super::check_table_permission(
+        database,
+        session_manager,
+        auth.token(),
+        form.table_id,
+        Permission::Import,
+    )
+    .await
+    {
+        return response;
+    }
     let mut path = config.temp_dir.clone();
     path.push(auth.token());
     if let (Ok(_), Some(p)) = (form.upload.persist_to(path).await, form.upload.path()) {
         match import(p, form.table_id, database, keypair).await {
-            Ok(_) => VaultResponse::flash_success_redirect_to(
-                rocket::uri!(super::table_cud::edit(form.table_id)),
-                "The selected file was imported",
-            ),
+            Ok(rows) => {
+                super::audit(
+                    database,
+                    events,
+                    session_manager,
+                    auth.token(),
+                    AuditRecord::new(AuditAction::Import)
+                        .with_table(form.table_id)
+                        .with_details(format!("{} rows", rows)),
+                )
+                .await;
+                VaultResponse::flash_success_redirect_to(
+                    rocket::uri!(super::table_cud::edit(form.table_id)),
+                    "The selected file was imported",
+                )
+            }
             Err(e) => match e {
                 ImportError::CsvError(e) => response_from_csv_err(e.kind(), form.table_id),
                 ImportError::ColumnMismatch => VaultResponse::flash_error_redirect_to(
@@ -73,13 +134,14 @@ enum ImportError {
     TableNotFound,
 }
 
+/// Imports all records of the given csv file and returns the number of imported rows
 // XXXX: This is pretty chunky, iterator magic though
 async fn import(
     path: &path::Path,
     table_id: u64,
     database: &VaultDb,
     keypair: &crypt::KeyPair,
-) -> Result<(), ImportError> {
+) -> Result<usize, ImportError> {
     let r = csv::Reader::from_path(path)
         .map_err(ImportError::CsvError)?
         .into_records();
@@ -123,7 +185,7 @@ async fn import(
             .await
             .map_err(|_| ImportError::DatabaseError)?;
     }
-    Ok(())
+    Ok(records.len())
 }
 
 fn response_from_csv_err<T>(err_kind: &csv::ErrorKind, table_id: u64) -> VaultResponse<T> {
@@ -1,22 +1,138 @@
+use crate::permissions::{Permission, Permissions};
+use crate::{VaultConfig, VaultDb};
 use rand::Rng;
 use rocket::tokio::sync;
 use rocket::{fairing, http, request};
+use sha2::Digest;
 use std::{collections, marker, time};
 
 pub const SESSION_TOKEN_COOKIE: &str = "_session_token";
+/// The prefix of personal access tokens, which distinguishes them from session tokens
+pub const ACCESS_TOKEN_PREFIX: &str = "spv_";
+const ACCESS_TOKEN_LENGTH: usize = 40;
+/// How long the session of a personal access token is valid, every request that sends the token renews it
+const ACCESS_TOKEN_SESSION_VALIDITY: time::Duration = time::Duration::from_secs(60);
+/// The name of the session cookie when the `__Host-` prefix is enabled
+pub const HOST_SESSION_TOKEN_COOKIE: &str = "__Host-session_token";
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<SameSite> for http::SameSite {
+    fn from(same_site: SameSite) -> Self {
+        match same_site {
+            SameSite::Strict => http::SameSite::Strict,
+            SameSite::Lax => http::SameSite::Lax,
+            SameSite::None => http::SameSite::None,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SessionCookieConfig {
+    /// Only allows the browser to send the cookie over https
+    #[serde(default)]
+    pub secure: bool,
+    /// Prefixes the cookie name with `__Host-`, which implies `secure`
+    #[serde(default)]
+    pub host_prefix: bool,
+    #[serde(default = "default_same_site")]
+    pub same_site: SameSite,
+}
+
+fn default_same_site() -> SameSite {
+    SameSite::Strict
+}
+
+impl Default for SessionCookieConfig {
+    fn default() -> Self {
+        Self {
+            secure: false,
+            host_prefix: false,
+            same_site: default_same_site(),
+        }
+    }
+}
+
+impl SessionCookieConfig {
+    pub fn name(&self) -> &'static str {
+        if self.host_prefix {
+            HOST_SESSION_TOKEN_COOKIE
+        } else {
+            SESSION_TOKEN_COOKIE
+        }
+    }
+
+    /// Builds the session cookie for the given token
+    ///
+    /// Browsers only accept cookies with the `__Host-` prefix if they are secure, have the path `/` and no domain.
+    pub fn build(&self, token: String, max_age_secs: u64) -> http::Cookie<'static> {
+        http::Cookie::build(self.name(), token)
+            .path("/")
+            .max_age(::time::Duration::seconds(max_age_secs as i64))
+            .http_only(true)
+            .secure(self.secure || self.host_prefix)
+            .same_site(self.same_site.into())
+            .finish()
+    }
+}
+
+#[derive(Clone)]
 pub struct SessionMetadata {
     pub expires: time::Instant,
-    pub admin: bool,
+    pub user_id: u64,
+    pub permissions: Permissions,
+    /// The token that has to be submitted with every state-changing form of this session
+    pub csrf_token: String,
+    /// Decides whether the session may take sensitive actions
+    pub confirmation: Confirmation,
+    /// The personal access token this session was created for
+    pub access_token: Option<u64>,
+    /// The only tables this session can access, all tables if it is `None`
+    pub tables: Option<Vec<u64>>,
 }
 
 impl SessionMetadata {
-    pub fn new(expires: time::Instant, admin: bool) -> Self {
-        SessionMetadata { expires, admin }
+    pub fn new(
+        expires: time::Instant,
+        user_id: u64,
+        permissions: Permissions,
+        csrf_token: String,
+        confirmation: Confirmation,
+    ) -> Self {
+        SessionMetadata {
+            expires,
+            user_id,
+            permissions,
+            csrf_token,
+            confirmation,
+            access_token: None,
+            tables: None,
+        }
+    }
+
+    pub fn is_confirmed_within(&self, duration: time::Duration) -> bool {
+        match self.confirmation {
+            Confirmation::Password(confirmed) => confirmed.elapsed() < duration,
+            Confirmation::AccessToken { decrypt } => decrypt,
+        }
     }
 }
 
+/// How a session proves that it may take sensitive actions, like decrypting values or deleting tables
+#[derive(Clone, Copy)]
+pub enum Confirmation {
+    /// The password was last entered at the given time, sensitive actions require a recent confirmation
+    Password(time::Instant),
+    /// Personal access tokens can't enter a password, so their `decrypt` scope decides instead
+    AccessToken { decrypt: bool },
+}
+
 type SessionMap = collections::HashMap<String, SessionMetadata>;
 
 pub struct SessionManager(SessionMap);
@@ -38,13 +154,21 @@ impl SessionManager {
         &mut self,
         token_len: usize,
         validity_duration: time::Duration,
-        admin: bool,
+        user_id: u64,
+        permissions: Permissions,
     ) -> (String, SessionMetadata) {
         let entry = (
             gen_random_token(token_len),
-            SessionMetadata::new(time::Instant::now() + validity_duration, admin),
+            SessionMetadata::new(
+                time::Instant::now() + validity_duration,
+                user_id,
+                permissions,
+                gen_random_token(token_len),
+                //Logging in counts as a confirmation
+                Confirmation::Password(time::Instant::now()),
+            ),
         );
-        self.0.insert(entry.0.clone(), entry.1);
+        self.0.insert(entry.0.clone(), entry.1.clone());
         entry
     }
 
@@ -52,13 +176,59 @@ impl SessionManager {
         self.0.get(key).map(|s| (key, s))
     }
 
+    pub fn get_session_metadata(&self, key: &str) -> Option<SessionMetadata> {
+        self.0.get(key).cloned()
+    }
+
+    /// Marks the session with the given key as recently confirmed by entering the password
+    ///
+    /// Returns `false` if there is no such session or if it is the session of a personal access token.
+    pub fn confirm_session(&mut self, key: &str) -> bool {
+        match self.0.get_mut(key) {
+            Some(session) if matches!(session.confirmation, Confirmation::Password(_)) => {
+                session.confirmation = Confirmation::Password(time::Instant::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Registers the session of a personal access token, which replaces an earlier session of the same token
+    ///
+    /// The expired sessions of other tokens are removed, they would otherwise stay until the vault is restarted.
+    pub fn insert_access_token_session(&mut self, key: String, session: SessionMetadata) {
+        let now = time::Instant::now();
+        self.0
+            .retain(|_, s| s.access_token.is_none() || s.expires > now);
+        self.0.insert(key, session);
+    }
+
+    /// Removes the session of the given personal access token
+    pub fn revoke_access_token(&mut self, token_id: u64) {
+        self.0.retain(|_, s| s.access_token != Some(token_id));
+    }
+
+    /// Removes the session with the given key, returns whether it existed
+    pub fn revoke_session(&mut self, key: &str) -> bool {
+        self.0.remove(key).is_some()
+    }
+
+    /// Removes all sessions of the given user, optionally except the session with the given key
+    pub fn revoke_user_sessions(&mut self, user_id: u64, except: Option<&str>) {
+        self.0
+            .retain(|k, s| s.user_id != user_id || Some(k.as_str()) == except);
+    }
+
     pub fn is_admin_session(&self, key: &str) -> Option<bool> {
-        self.get_session(key).map(|s| s.1.admin)
+        self.has_permission(key, Permission::Administrate)
+    }
+
+    pub fn get_permissions(&self, key: &str) -> Option<Permissions> {
+        self.get_session(key).map(|s| s.1.permissions)
     }
 
-    pub fn is_session_valid(&self, key: &str) -> Option<bool> {
-        self.get_session(key)
-            .map(|s| s.1.expires > time::Instant::now())
+    pub fn has_permission(&self, key: &str, permission: Permission) -> Option<bool> {
+        self.get_permissions(key).map(|p| p.contains(permission))
     }
 }
 
@@ -70,12 +240,17 @@ fn gen_random_token(len: usize) -> String {
         .collect()
 }
 
-pub struct TokenAuth<M>(String, marker::PhantomData<M>);
+pub struct TokenAuth<M>(String, String, marker::PhantomData<M>);
 
 impl<M> TokenAuth<M> {
     pub fn token(&self) -> &str {
         &self.0
     }
+
+    /// The csrf token of the session, which has to be rendered into every state-changing form
+    pub fn csrf_token(&self) -> &str {
+        &self.1
+    }
 }
 
 pub type TokenAuthResult<M> = Result<TokenAuth<M>, TokenAuthError>;
@@ -83,18 +258,27 @@ pub type TokenAuthResult<M> = Result<TokenAuth<M>, TokenAuthError>;
 #[derive(Debug)]
 pub enum TokenAuthError {
     NoTokenSpecified,
+    /// The `Authorization` header is not a single `Bearer` token
+    MalformedHeader,
     NoSuchToken,
     ExpiredToken,
+    /// A personal access token was sent to a route that only accepts sessions
+    AccessTokenNotAllowed,
     NoSessionManager,
+    /// A personal access token couldn't be checked
+    Database,
 }
 
 impl<M: AuthMethod> From<TokenAuthError> for request::Outcome<TokenAuth<M>, TokenAuthError> {
     fn from(error: TokenAuthError) -> Self {
         let status = match error {
             TokenAuthError::NoTokenSpecified => http::Status::BadRequest,
+            TokenAuthError::MalformedHeader => http::Status::BadRequest,
             TokenAuthError::NoSuchToken => http::Status::Unauthorized,
             TokenAuthError::ExpiredToken => http::Status::Unauthorized,
+            TokenAuthError::AccessTokenNotAllowed => http::Status::Forbidden,
             TokenAuthError::NoSessionManager => http::Status::InternalServerError,
+            TokenAuthError::Database => http::Status::InternalServerError,
         };
         request::Outcome::Failure((status, error))
     }
@@ -108,48 +292,259 @@ impl<'r, M: AuthMethod> request::FromRequest<'r> for TokenAuth<M> {
         request: &'r request::Request<'_>,
     ) -> request::Outcome<Self, Self::Error> {
         match request.rocket().state::<SafeSessionManager>() {
-            Some(manager) => match M::retrieve_token(request) {
-                Some(token) => match manager.lock().await.is_session_valid(&token) {
-                    Some(valid) => {
-                        if !valid {
+            Some(manager) => match M::retrieve_token(request).await {
+                Ok(token) => match manager.lock().await.get_session_metadata(&token) {
+                    Some(session) => {
+                        if session.expires <= time::Instant::now() {
                             return Self::Error::ExpiredToken.into();
                         }
-                        request::Outcome::Success(Self(token, marker::PhantomData))
+                        request::Outcome::Success(Self(
+                            token,
+                            session.csrf_token,
+                            marker::PhantomData,
+                        ))
                     }
                     None => Self::Error::NoSuchToken.into(),
                 },
-                None => Self::Error::NoTokenSpecified.into(),
+                Err(e) => e.into(),
             },
             None => Self::Error::NoSessionManager.into(),
         }
     }
 }
 
+#[rocket::async_trait]
 pub trait AuthMethod {
-    fn retrieve_token(request: &request::Request) -> Option<String>;
+    async fn retrieve_token(request: &request::Request<'_>) -> Result<String, TokenAuthError>;
 }
 
 pub struct WithCookie;
 
+#[rocket::async_trait]
 impl AuthMethod for WithCookie {
-    fn retrieve_token(request: &request::Request) -> Option<String> {
+    async fn retrieve_token(request: &request::Request<'_>) -> Result<String, TokenAuthError> {
+        let name = request
+            .rocket()
+            .state::<VaultConfig>()
+            .map_or(SESSION_TOKEN_COOKIE, |c| c.session_cookie.name());
         request
             .cookies()
-            .get(SESSION_TOKEN_COOKIE)
+            .get(name)
             .map(|c| c.value().to_string())
+            .ok_or(TokenAuthError::NoTokenSpecified)
     }
 }
 
+/// Reads the session token from an `Authorization: Bearer <token>` header
+///
+/// Personal access tokens are refused, they are only accepted by routes that use [`WithAccessToken`].
 pub struct WithHeader;
 
+#[rocket::async_trait]
 impl AuthMethod for WithHeader {
-    fn retrieve_token(request: &request::Request) -> Option<String> {
-        request
-            .headers()
-            .get_one("Authorization")?
-            .split("Basic ")
-            .collect::<Vec<&str>>()
-            .first()
-            .map(|s| s.to_string())
+    async fn retrieve_token(request: &request::Request<'_>) -> Result<String, TokenAuthError> {
+        let token = bearer_token(request)?;
+        if token.starts_with(ACCESS_TOKEN_PREFIX) {
+            return Err(TokenAuthError::AccessTokenNotAllowed);
+        }
+        Ok(token)
+    }
+}
+
+/// Returns the token of the `Authorization` header, which has to be a single `Bearer` token
+fn bearer_token(request: &request::Request<'_>) -> Result<String, TokenAuthError> {
+    let mut headers = request.headers().get("Authorization");
+    let header = headers.next().ok_or(TokenAuthError::NoTokenSpecified)?;
+    if headers.next().is_some() {
+        return Err(TokenAuthError::MalformedHeader);
+    }
+    parse_bearer_header(header)
+        .map(str::to_string)
+        .ok_or(TokenAuthError::MalformedHeader)
+}
+
+/// Returns the token of an `Authorization: Bearer <token>` header value
+fn parse_bearer_header(header: &str) -> Option<&str> {
+    //The scheme is case-insensitive, the token consists of the characters of RFC 6750
+    match header.split_once(' ') {
+        Some((scheme, token))
+            if scheme.eq_ignore_ascii_case("Bearer")
+                && !token.is_empty()
+                && token
+                    .trim_end_matches('=')
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-._~+/".contains(c)) =>
+        {
+            Some(token)
+        }
+        _ => None,
+    }
+}
+
+/// Reads the token from the `Authorization` header if there is one, from the session cookie otherwise
+///
+/// Used by routes that are called by browsers as well as by scripts.
+pub struct WithCookieOrHeader;
+
+#[rocket::async_trait]
+impl AuthMethod for WithCookieOrHeader {
+    async fn retrieve_token(request: &request::Request<'_>) -> Result<String, TokenAuthError> {
+        match WithHeader::retrieve_token(request).await {
+            Err(TokenAuthError::NoTokenSpecified) => WithCookie::retrieve_token(request).await,
+            result => result,
+        }
+    }
+}
+
+/// Reads the token from the `Authorization` header, which can also be a personal access token
+///
+/// Personal access tokens are checked against the database on every request. They are then registered as a short-lived
+/// session, whose permissions are those of the account restricted to the scopes of the token.
+pub struct WithAccessToken;
+
+#[rocket::async_trait]
+impl AuthMethod for WithAccessToken {
+    async fn retrieve_token(request: &request::Request<'_>) -> Result<String, TokenAuthError> {
+        let token = bearer_token(request)?;
+        register_access_token(request, token).await
+    }
+}
+
+/// Reads the token from the `X-Vault-Token` header of HashiCorp Vault clients, which can also be a personal access token
+pub struct WithVaultToken;
+
+#[rocket::async_trait]
+impl AuthMethod for WithVaultToken {
+    async fn retrieve_token(request: &request::Request<'_>) -> Result<String, TokenAuthError> {
+        let mut headers = request.headers().get("X-Vault-Token");
+        let token = headers.next().ok_or(TokenAuthError::NoTokenSpecified)?;
+        if token.is_empty() || headers.next().is_some() {
+            return Err(TokenAuthError::MalformedHeader);
+        }
+        register_access_token(request, token.to_string()).await
+    }
+}
+
+/// Registers the session of a personal access token, other tokens are returned unchanged
+async fn register_access_token(
+    request: &request::Request<'_>,
+    token: String,
+) -> Result<String, TokenAuthError> {
+    if !token.starts_with(ACCESS_TOKEN_PREFIX) {
+        return Ok(token);
+    }
+    let rocket = request.rocket();
+    let (manager, database, config) = match (
+        rocket.state::<SafeSessionManager>(),
+        rocket.state::<VaultDb>(),
+        rocket.state::<VaultConfig>(),
+    ) {
+        (Some(manager), Some(database), Some(config)) => (manager, database, config),
+        _ => return Err(TokenAuthError::NoSessionManager),
+    };
+    let access_token = match database
+        .fetch_access_token_by_hash(&hash_access_token(&token))
+        .await
+    {
+        Ok(Some(access_token)) => access_token,
+        Ok(None) => return Err(TokenAuthError::NoSuchToken),
+        Err(_) => return Err(TokenAuthError::Database),
+    };
+    let now = time::Instant::now();
+    let mut expires = now + ACCESS_TOKEN_SESSION_VALIDITY;
+    if let Some(token_expires) = access_token.expires {
+        match (token_expires - chrono::Utc::now()).to_std() {
+            Ok(remaining) => expires = expires.min(now + remaining),
+            Err(_) => return Err(TokenAuthError::ExpiredToken),
+        }
+    }
+    let account = match database.fetch_password_by_id(access_token.auth_id).await {
+        Ok(Some(account)) if !account.disabled && !account.password_reset => account,
+        Ok(_) => return Err(TokenAuthError::NoSuchToken),
+        Err(_) => return Err(TokenAuthError::Database),
+    };
+    let permissions = match database
+        .fetch_permissions(&account, &config.default_role)
+        .await
+    {
+        Ok(permissions) => permissions.intersection(access_token.permissions),
+        Err(_) => return Err(TokenAuthError::Database),
+    };
+    let tables = if access_token.all_tables {
+        None
+    } else {
+        match database.fetch_access_token_table_ids(access_token.id).await {
+            Ok(tables) => Some(tables),
+            Err(_) => return Err(TokenAuthError::Database),
+        }
+    };
+    if database
+        .update_access_token_last_used(access_token.id)
+        .await
+        .is_err()
+    {
+        rocket::warn!(
+            "Failed to update the last use of access token {}",
+            access_token.id
+        );
+    }
+
+    let mut session = SessionMetadata::new(
+        expires,
+        account.id,
+        permissions,
+        gen_random_token(config.token_length as usize),
+        Confirmation::AccessToken {
+            decrypt: access_token.decrypt,
+        },
+    );
+    session.access_token = Some(access_token.id);
+    session.tables = tables;
+    manager
+        .lock()
+        .await
+        .insert_access_token_session(token.clone(), session);
+    Ok(token)
+}
+
+/// Generates a new personal access token, which is recognizable by its prefix
+pub fn gen_access_token() -> String {
+    format!(
+        "{}{}",
+        ACCESS_TOKEN_PREFIX,
+        gen_random_token(ACCESS_TOKEN_LENGTH)
+    )
+}
+
+/// Returns the hash of a personal access token, which is stored instead of the token itself
+pub fn hash_access_token(token: &str) -> String {
+    hex::encode(sha2::Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_bearer_header;
+
+    #[test]
+    fn parses_bearer_tokens() {
+        assert_eq!(parse_bearer_header("Bearer abc123"), Some("abc123"));
+        assert_eq!(parse_bearer_header("bearer abc123"), Some("abc123"));
+        assert_eq!(
+            parse_bearer_header("BEARER a-b.c_d~e+f/g=="),
+            Some("a-b.c_d~e+f/g==")
+        );
+        assert_eq!(parse_bearer_header("Bearer spv_abc"), Some("spv_abc"));
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert_eq!(parse_bearer_header("abc123"), None);
+        assert_eq!(parse_bearer_header("Bearer"), None);
+        assert_eq!(parse_bearer_header("Bearer "), None);
+        assert_eq!(parse_bearer_header("Basic YWRtaW46cGFzc3dvcmQ="), None);
+        assert_eq!(parse_bearer_header("Bearer abc 123"), None);
+        assert_eq!(parse_bearer_header("Bearer  abc123"), None);
+        assert_eq!(parse_bearer_header("Bearer abc=123"), None);
+        assert_eq!(parse_bearer_header("Bearer abc\n123"), None);
     }
 }